@@ -0,0 +1,70 @@
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::ops::{Sub, SubAssign};
+use std::str::FromStr;
+
+/// An unsigned integer usable as the counter of an [`EventTree`](crate::EventTree).
+///
+/// Implemented for `u8`, `u16`, `u32`, `u64` and `u128`. Narrower counters give smaller stamps
+/// but overflow sooner; see [`OverflowPolicy`](crate::OverflowPolicy).
+pub trait Counter:
+    Copy
+    + Ord
+    + Hash
+    + Default
+    + Debug
+    + Display
+    + FromStr
+    + Sub<Output = Self>
+    + SubAssign
+    + Send
+    + Sync
+    + 'static
+{
+    const ZERO: Self;
+    const ONE: Self;
+    const MAX: Self;
+    /// Width of the counter in bits, bounding the values accepted when decoding.
+    const BITS: u32;
+
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn saturating_add(self, rhs: Self) -> Self;
+    fn saturating_sub(self, rhs: Self) -> Self;
+    fn to_u128(self) -> u128;
+    fn from_u128(n: u128) -> Option<Self>;
+}
+
+macro_rules! impl_counter {
+    ($($t:ty),*) => {
+        $(
+            impl Counter for $t {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const MAX: Self = <$t>::MAX;
+                const BITS: u32 = <$t>::BITS;
+
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$t>::checked_add(self, rhs)
+                }
+
+                fn saturating_add(self, rhs: Self) -> Self {
+                    <$t>::saturating_add(self, rhs)
+                }
+
+                fn saturating_sub(self, rhs: Self) -> Self {
+                    <$t>::saturating_sub(self, rhs)
+                }
+
+                fn to_u128(self) -> u128 {
+                    self as u128
+                }
+
+                fn from_u128(n: u128) -> Option<Self> {
+                    n.try_into().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_counter!(u8, u16, u32, u64, u128);
@@ -0,0 +1,284 @@
+//! Bit-level primitives for the compact encoding defined in section 6 of the original paper.
+//!
+//! Values are written most-significant bit first and the final byte is padded with zeros.
+use crate::Counter;
+use thiserror::Error;
+
+/// The default maximum nesting depth accepted when decoding or deserializing, guarding against
+/// crafted inputs.
+///
+/// `encode` and serialization have no such limit, so deeper trees have to be read back with a
+/// larger [`DecodeOptions::max_depth`].
+pub const MAX_DECODE_DEPTH: usize = 512;
+
+/// Options controlling how the compact encoding is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub(crate) max_depth: usize,
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of nested subtrees allowed before decoding fails.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: MAX_DECODE_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    #[error("Unexpected end of input at bit {0}")]
+    UnexpectedEnd(usize),
+
+    #[error("Counter at bit {0} does not fit in the counter type")]
+    Overflow(usize),
+
+    #[error("Trailing data after bit {0}")]
+    TrailingData(usize),
+
+    #[error("Maximum nesting depth exceeded at bit {0}")]
+    TooDeep(usize),
+
+    #[error("Invalid value at bit {0}")]
+    InvalidValue(usize),
+}
+
+/// Which children of a node follow it in the bit stream, any others being zero.
+pub(crate) enum Children {
+    Left,
+    Right,
+    Both,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the lowest `width` bits of `value`.
+    pub(crate) fn write_bits(&mut self, value: u64, width: u32) {
+        for shift in (0..width).rev() {
+            self.write_bit((value >> shift) & 1 == 1);
+        }
+    }
+
+    pub(crate) fn write_bit(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_bits(*byte as u64, 8);
+        }
+    }
+
+    /// enc(n) = ⟨1, 1⟩ enc(n, 2)
+    pub(crate) fn write_counter<C: Counter>(&mut self, n: C) {
+        self.write_bit(true);
+
+        // enc(n, B) = ⟨0, 1⟩⟨n, B⟩ if n < 2^B, otherwise ⟨1, 1⟩ enc(n - 2^B, B + 1)
+        let mut n = n.to_u128();
+        let mut width = 2;
+        while 1u128.checked_shl(width).is_some_and(|bound| n >= bound) {
+            self.write_bit(true);
+            n -= 1 << width;
+            width += 1;
+        }
+        self.write_bit(false);
+        for shift in (0..width).rev() {
+            self.write_bit((n >> shift) & 1 == 1);
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    max_depth: usize,
+}
+
+impl<'a> BitReader<'a> {
+    #[cfg(test)]
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self::with_options(bytes, &DecodeOptions::default())
+    }
+
+    pub(crate) fn with_options(bytes: &'a [u8], options: &DecodeOptions) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            max_depth: options.max_depth,
+        }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn read_bit(&mut self) -> Result<bool, DecodeError> {
+        let byte = self
+            .bytes
+            .get(self.pos / 8)
+            .ok_or(DecodeError::UnexpectedEnd(self.pos))?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    pub(crate) fn read_bits(&mut self, width: u32) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for _ in 0..width {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, DecodeError> {
+        (0..len).map(|_| Ok(self.read_bits(8)? as u8)).collect()
+    }
+
+    pub(crate) fn read_counter<C: Counter>(&mut self) -> Result<C, DecodeError> {
+        let start = self.pos;
+        if !self.read_bit()? {
+            return Err(DecodeError::InvalidValue(start));
+        }
+        self.read_number()
+    }
+
+    /// Reads enc(n, 2), i.e. a counter without its leading ⟨1, 1⟩ marker.
+    pub(crate) fn read_number<C: Counter>(&mut self) -> Result<C, DecodeError> {
+        let start = self.pos;
+        let mut base: u128 = 0;
+        let mut width = 2;
+        while self.read_bit()? {
+            base += 1 << width;
+            width += 1;
+            if width > C::BITS {
+                return Err(DecodeError::Overflow(start));
+            }
+        }
+
+        let mut n: u128 = 0;
+        for _ in 0..width {
+            n = (n << 1) | self.read_bit()? as u128;
+        }
+        base.checked_add(n)
+            .and_then(C::from_u128)
+            .ok_or(DecodeError::Overflow(start))
+    }
+
+    /// Fails if a subtree with `depth` subtrees above it is nested too deep.
+    pub(crate) fn check_depth(&self, depth: usize) -> Result<(), DecodeError> {
+        if depth >= self.max_depth {
+            return Err(DecodeError::TooDeep(self.pos));
+        }
+        Ok(())
+    }
+
+    /// Ensures only zero padding remains in the final byte.
+    pub(crate) fn finish(mut self) -> Result<(), DecodeError> {
+        let end = self.pos;
+        if self.bytes.len() > end.div_ceil(8) {
+            return Err(DecodeError::TrailingData(end));
+        }
+        while !self.pos.is_multiple_of(8) {
+            if self.read_bit()? {
+                return Err(DecodeError::TrailingData(end));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_roundtrip() {
+        for n in [
+            0,
+            1,
+            3,
+            4,
+            11,
+            12,
+            1000,
+            u32::MAX as u64,
+            u64::MAX - 1,
+            u64::MAX,
+        ] {
+            let mut writer = BitWriter::new();
+            writer.write_counter(n);
+            let bytes = writer.finish();
+
+            let mut reader = BitReader::new(&bytes);
+            assert_eq!(reader.read_counter(), Ok(n));
+            reader.finish().expect("Only padding remains");
+        }
+    }
+
+    #[test]
+    fn test_counter_widths() {
+        for n in [0, 5, u32::MAX as u128, u64::MAX as u128 + 1, u128::MAX] {
+            let mut writer = BitWriter::new();
+            writer.write_counter(n);
+            let bytes = writer.finish();
+
+            let mut reader = BitReader::new(&bytes);
+            assert_eq!(reader.read_counter(), Ok(n));
+
+            let mut reader = BitReader::new(&bytes);
+            let narrow = reader.read_counter::<u32>();
+            match u32::try_from(n) {
+                Ok(n) => assert_eq!(narrow, Ok(n)),
+                Err(_) => assert_eq!(narrow, Err(DecodeError::Overflow(1))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_counter_golden() {
+        // n = 5: ⟨1,1⟩ then 5 >= 2^2 so ⟨1,1⟩ enc(1, 3) = ⟨0,1⟩⟨1,3⟩
+        let mut writer = BitWriter::new();
+        writer.write_counter(5u64);
+        assert_eq!(writer.finish(), vec![0b1100_0100]);
+    }
+
+    #[test]
+    fn test_counter_overflow() {
+        // A run of 64 continuation bits can never fit in a u64.
+        let bytes = [0xff; 10];
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_counter::<u64>(), Err(DecodeError::Overflow(1)));
+    }
+}
@@ -1,322 +1,1284 @@
-use crate::IdTree;
+use crate::{Counter, IdTree};
 use std::cmp::Ordering;
+use thiserror::Error;
+
+mod encoding;
+mod flat;
+mod growth;
+#[cfg(feature = "parse")]
+pub(crate) mod parser;
+mod regions;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod shared;
+
+pub use flat::FlatEventTree;
+pub use growth::{GrowthStrategy, MinDepthGrowth, MinSizeGrowth, PaperGrowth};
+pub use shared::{EventInterner, SharedEventTree};
+
+/// The causal relationship of one timestamp to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Relation {
+    /// Every event seen by `self` was also seen by `other`, which has seen more.
+    HappenedBefore,
+    /// Every event seen by `other` was also seen by `self`, which has seen more.
+    HappenedAfter,
+    Equal,
+    /// Each side has seen events the other has not.
+    Concurrent,
+}
+
+impl From<Relation> for Option<Ordering> {
+    fn from(relation: Relation) -> Self {
+        match relation {
+            Relation::HappenedBefore => Some(Ordering::Less),
+            Relation::HappenedAfter => Some(Ordering::Greater),
+            Relation::Equal => Some(Ordering::Equal),
+            Relation::Concurrent => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum EventError {
+    #[error("Cannot record an event with an anonymous id")]
+    AnonymousId,
+
+    #[error("Cannot record an event on a read-only replica")]
+    ReadOnly,
+
+    #[error(transparent)]
+    Overflow(#[from] OverflowError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Counter overflowed")]
+pub struct OverflowError;
+
+/// How an overflowing counter is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Fail the operation, leaving the tree as it was.
+    #[default]
+    Error,
+    /// Clamp counters at the maximum value.
+    Saturate,
+}
 
 /// A near one-to-one replication of the original paper.
-#[derive(Clone, Debug)]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub enum EventTree {
-    Leaf(u64),
-    SubTree(u64, Box<EventTree>, Box<EventTree>),
+///
+/// Dropping a tree recurses once per level, so a tree many thousands of levels deep can overflow
+/// the stack when dropped; free those with [`EventTree::drop_iteratively`].
+#[derive(Debug)]
+pub enum EventTree<C: Counter = u64> {
+    Leaf(C),
+    SubTree(C, Box<EventTree<C>>, Box<EventTree<C>>),
 }
 
 impl EventTree {
+    /// Creates an empty `u64` timestamp; other counter widths start from `EventTree::default()`.
     pub fn new() -> Self {
         Self::default()
     }
+}
+
+impl<C: Counter> EventTree<C> {
+    /// Builds a subtree, normalizing it so that equal timestamps share a single representation.
+    pub fn subtree(val: C, left: Self, right: Self) -> Self {
+        Self::node(val, left.normalize(), right.normalize())
+    }
 
-    pub fn subtree(val: u64, left: EventTree, right: EventTree) -> Self {
-        Self::SubTree(val, Box::new(left), Box::new(right))
+    pub fn join(mut self, other: Self) -> Self {
+        self.join_assign(&other);
+        other.drop_iteratively();
+        self
     }
 
-    pub fn join(self, other: Self) -> Self {
+    /// Joins `other` into `self`, reusing the existing nodes and only allocating where `other`
+    /// has more structure.
+    pub fn join_assign(&mut self, other: &Self) {
         use EventTree::*;
-        match (self, other) {
-            (Leaf(a), Leaf(b)) => Leaf(a.max(b)),
-            (l @ Leaf(a), r @ SubTree(b, _, _))
-            | (l @ SubTree(a, _, _), r @ Leaf(b))
-            | (l @ SubTree(a, _, _), r @ SubTree(b, _, _))
-                if a > b =>
+
+        enum Frame<'a, C: Counter> {
+            /// Join the second tree, lifted by the base, into the first.
+            Visit(EventTree<C>, &'a EventTree<C>, C),
+            /// Restore the two most recently joined children into the node.
+            Node(EventTree<C>),
+        }
+
+        let zero = Leaf(C::ZERO);
+        let mut frames = vec![Frame::Visit(std::mem::take(self), other, C::ZERO)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let (mut a, b, base) = match frame {
+                Frame::Visit(a, b, base) => (a, b, base),
+                Frame::Node(mut a) => {
+                    let (l, r) = Self::pop_pair(&mut done);
+                    a.put_children(l, r);
+                    a.norm_root();
+                    done.push(a);
+                    continue;
+                }
+            };
+
+            match (&mut a, b) {
+                (Leaf(x), Leaf(y)) => *x = (*x).max(base.saturating_add(*y)),
+                (Leaf(x), SubTree(y, _, _)) if *x <= base.saturating_add(*y) => a = b.lifted(base),
+                (Leaf(x), SubTree(..)) if *x >= base.saturating_add(b.max()) => {}
+                (SubTree(x, _, _), Leaf(y)) if base.saturating_add(*y) <= *x => {}
+                _ => {
+                    if let Leaf(x) = a {
+                        a = SubTree(x, Box::new(Leaf(C::ZERO)), Box::new(Leaf(C::ZERO)));
+                    }
+
+                    let (y, bl, br) = match b {
+                        Leaf(y) => (*y, &zero, &zero),
+                        SubTree(y, l, r) => (*y, l.as_ref(), r.as_ref()),
+                    };
+
+                    // Rebase our children on the smaller of the two roots so the other side is
+                    // only ever lifted.
+                    let other_base = base.saturating_add(y);
+                    let (mut l, mut r) = a.take_children();
+                    let x = a.value_mut();
+                    if *x > other_base {
+                        l.lift_root(*x - other_base);
+                        r.lift_root(*x - other_base);
+                        *x = other_base;
+                    }
+                    let child_base = other_base - *x;
+
+                    frames.push(Frame::Node(a));
+                    frames.push(Frame::Visit(r, br, child_base));
+                    frames.push(Frame::Visit(l, bl, child_base));
+                    continue;
+                }
+            }
+            done.push(a);
+        }
+        *self = done.pop().expect("The root is always joined");
+    }
+
+    /// Joins `other`, handling counters which overflow in either tree according to `policy`.
+    pub fn try_join(mut self, other: Self, policy: OverflowPolicy) -> Result<Self, OverflowError> {
+        let joined = self.try_join_assign(&other, policy);
+        other.drop_iteratively();
+        joined.map(|_| self)
+    }
+
+    /// Joins `other` into `self`, handling counters which overflow in either tree according to
+    /// `policy`. Failing leaves `self` as it was.
+    pub fn try_join_assign(
+        &mut self,
+        other: &Self,
+        policy: OverflowPolicy,
+    ) -> Result<(), OverflowError> {
+        // Every value of the join comes from one of the two trees, so joining valid trees never
+        // saturates.
+        match policy {
+            OverflowPolicy::Error => {
+                self.validate()?;
+                other.validate()?;
+                self.join_assign(other);
+            }
+            OverflowPolicy::Saturate => {
+                if self.validate().is_err() {
+                    self.saturate();
+                }
+                if other.validate().is_ok() {
+                    self.join_assign(other);
+                } else {
+                    let mut other = other.clone();
+                    other.saturate();
+                    self.join_assign(&other);
+                    other.drop_iteratively();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Joins without consuming either side; only the resulting tree is allocated.
+    pub fn join_ref(&self, other: &Self) -> Self {
+        use EventTree::*;
+        Self::zip_with(self, other, |a, a_base, b, b_base| match (a, b) {
+            (Leaf(x), Leaf(y)) => Zip::Done(Leaf(
+                a_base.saturating_add(*x).max(b_base.saturating_add(*y)),
+            )),
+            (Leaf(x), SubTree(y, _, _))
+                if a_base.saturating_add(*x) <= b_base.saturating_add(*y) =>
             {
-                r.join(l)
+                Zip::Done(b.lifted(b_base))
             }
-            (Leaf(_), r @ SubTree(_, _, _)) => r,
-            (l @ SubTree(_, _, _), Leaf(b)) => {
-                l.join(SubTree(b, Box::new(Leaf(0)), Box::new(Leaf(0))))
+            (SubTree(x, _, _), Leaf(y))
+                if b_base.saturating_add(*y) <= a_base.saturating_add(*x) =>
+            {
+                Zip::Done(a.lifted(a_base))
             }
-            (SubTree(a, l0, r0), SubTree(b, l1, r1)) => SubTree(
-                a,
-                Box::new(l0.join(l1.lift(b - a))),
-                Box::new(r0.join(r1.lift(b - a))),
-            )
-            .norm(),
+            _ => Zip::Split,
+        })
+    }
+
+    /// The greatest lower bound: everything both timestamps have seen.
+    pub fn meet(&self, other: &Self) -> Self {
+        use EventTree::*;
+        Self::zip_with(self, other, |a, a_base, b, b_base| match (a, b) {
+            (Leaf(x), Leaf(y)) => Zip::Done(Leaf(
+                a_base.saturating_add(*x).min(b_base.saturating_add(*y)),
+            )),
+            (Leaf(x), SubTree(y, _, _))
+                if a_base.saturating_add(*x) <= b_base.saturating_add(*y) =>
+            {
+                Zip::Done(a.lifted(a_base))
+            }
+            (SubTree(x, _, _), Leaf(y))
+                if b_base.saturating_add(*y) <= a_base.saturating_add(*x) =>
+            {
+                Zip::Done(b.lifted(b_base))
+            }
+            _ => Zip::Split,
+        })
+    }
+
+    /// Joins any number of timestamps in a single pass over all of them, rather than building
+    /// the join of each pair in turn. Joining none gives the initial timestamp.
+    pub fn join_all<'a, I>(trees: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Self>,
+        C: 'a,
+    {
+        Self::zip_all(trees.into_iter().collect(), Bound::Upper).unwrap_or_default()
+    }
+
+    /// The meet of any number of timestamps in a single pass over all of them, or `None` when
+    /// there are none.
+    pub fn meet_all<'a, I>(trees: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = &'a Self>,
+        C: 'a,
+    {
+        Self::zip_all(trees.into_iter().collect(), Bound::Lower)
+    }
+
+    /// Walks all trees in lockstep, dropping the ones which cannot affect the bound below each
+    /// node and copying the rest of a tree once it is the only one left.
+    fn zip_all(trees: Vec<&Self>, bound: Bound) -> Option<Self> {
+        use EventTree::*;
+
+        if trees.is_empty() {
+            return None;
         }
+
+        // Stands in for a leaf, with its value carried as the base
+        let zero = Leaf(C::ZERO);
+        let mut frames = vec![Some(
+            trees.into_iter().map(|e| (e, C::ZERO)).collect::<Vec<_>>(),
+        )];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some(members) = frame else {
+                let (l, r) = Self::pop_pair(&mut done);
+                done.push(Self::node(C::ZERO, l, r));
+                continue;
+            };
+
+            // Only the most extreme leaf matters, and a subtree's root bounds all of its values
+            // from below, making either redundant for one of the bounds
+            let leaf = members
+                .iter()
+                .filter_map(|(e, base)| match e {
+                    Leaf(val) => Some(base.saturating_add(*val)),
+                    SubTree(..) => None,
+                })
+                .reduce(|a, b| match bound {
+                    Bound::Upper => a.max(b),
+                    Bound::Lower => a.min(b),
+                });
+            let mut subtrees: Vec<_> = members
+                .into_iter()
+                .filter(|(e, base)| match (e, leaf) {
+                    (Leaf(_), _) => false,
+                    (SubTree(val, _, _), Some(leaf)) if bound == Bound::Lower => {
+                        base.saturating_add(*val) < leaf
+                    }
+                    (SubTree(..), _) => true,
+                })
+                .collect();
+            let leaf = leaf.filter(|leaf| match bound {
+                Bound::Upper => subtrees
+                    .iter()
+                    .all(|(e, base)| base.saturating_add(e.value()) < *leaf),
+                Bound::Lower => true,
+            });
+
+            match (leaf, subtrees.len()) {
+                (Some(leaf), 0) => done.push(Leaf(leaf)),
+                (None, 1) => {
+                    let (e, base) = subtrees.pop().expect("A single subtree");
+                    done.push(e.lifted(base));
+                }
+                _ => {
+                    let mut left = Vec::with_capacity(subtrees.len() + 1);
+                    let mut right = Vec::with_capacity(subtrees.len() + 1);
+                    for (e, base) in subtrees {
+                        if let SubTree(val, l, r) = e {
+                            let base = base.saturating_add(*val);
+                            left.push((l.as_ref(), base));
+                            right.push((r.as_ref(), base));
+                        }
+                    }
+                    if let Some(leaf) = leaf {
+                        left.push((&zero, leaf));
+                        right.push((&zero, leaf));
+                    }
+                    frames.push(None);
+                    frames.push(Some(right));
+                    frames.push(Some(left));
+                }
+            }
+        }
+        done.pop()
     }
 
-    pub fn event(self, id: &IdTree) -> Self {
-        let fill = self.fill(id);
-        if fill == self {
-            #[allow(non_snake_case)]
-            let N = self.depth(0);
-            let (tree, _) = self.grow(id, N + 1);
-            tree
-        } else {
-            fill
+    /// Records an event for `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is anonymous; see [`EventTree::try_event`].
+    pub fn event(mut self, id: &IdTree) -> Self {
+        self.event_in_place(id);
+        self
+    }
+
+    /// Records an event for `id`, failing if the id owns no part of the interval.
+    pub fn try_event(mut self, id: &IdTree) -> Result<Self, EventError> {
+        self.try_event_in_place(id)?;
+        Ok(self)
+    }
+
+    /// Records an event for `id`, mutating the existing nodes and only allocating where the tree
+    /// has to grow.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is anonymous; see [`EventTree::try_event_in_place`].
+    pub fn event_in_place(&mut self, id: &IdTree) {
+        if let Err(e) = self.try_event_in_place(id) {
+            panic!("{e}");
         }
     }
 
-    /// Saturating substraction of the other EventTree
+    pub fn try_event_in_place(&mut self, id: &IdTree) -> Result<(), EventError> {
+        self.try_event_in_place_with(id, OverflowPolicy::Error)
+    }
+
+    /// Records an event for `id`, handling a counter which would overflow according to `policy`.
+    /// A saturated event leaves the tree unchanged.
+    pub fn try_event_in_place_with(
+        &mut self,
+        id: &IdTree,
+        policy: OverflowPolicy,
+    ) -> Result<(), EventError> {
+        self.try_event_n_in_place_with(id, C::ONE, policy)
+    }
+
+    /// Records `n` events for `id` in one step, giving the same tree as `n` calls to
+    /// [`EventTree::event`].
     ///
-    /// This uses a rather naive algorithm that forces each tree to be identical in structure and
-    /// then does a saturating_sub of the leaves. There is likely a more efficient algorithm.
-    pub fn diff(self, other: &Self) -> Self {
+    /// # Panics
+    ///
+    /// Panics if `id` is anonymous or a counter would overflow; see
+    /// [`EventTree::try_event_n_in_place_with`].
+    pub fn event_n(mut self, id: &IdTree, n: C) -> Self {
+        if let Err(e) = self.try_event_n_in_place_with(id, n, OverflowPolicy::Error) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Records `n` events for `id` in one step, handling a counter which would overflow
+    /// according to `policy`. Saturating records as many of the events as fit, while failing
+    /// leaves the tree as it was even if some of them would have fit.
+    pub fn try_event_n_in_place_with(
+        &mut self,
+        id: &IdTree,
+        n: C,
+        policy: OverflowPolicy,
+    ) -> Result<(), EventError> {
+        self.event_n_in_place(id, n, policy, &PaperGrowth)
+    }
+
+    /// Records an event for `id`, growing the tree where `strategy` prefers when it cannot be
+    /// filled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is anonymous or a counter would overflow; see
+    /// [`EventTree::try_event_in_place_with_strategy`].
+    pub fn event_with_strategy<S: GrowthStrategy<C>>(mut self, id: &IdTree, strategy: &S) -> Self {
+        if let Err(e) = self.try_event_in_place_with_strategy(id, OverflowPolicy::Error, strategy) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Records an event for `id`, growing the tree where `strategy` prefers when it cannot be
+    /// filled, and handling a counter which would overflow according to `policy`.
+    pub fn try_event_in_place_with_strategy<S: GrowthStrategy<C>>(
+        &mut self,
+        id: &IdTree,
+        policy: OverflowPolicy,
+        strategy: &S,
+    ) -> Result<(), EventError> {
+        self.event_n_in_place(id, C::ONE, policy, strategy)
+    }
+
+    /// Records `n` events one at a time, except that once filling stops, a strategy which keeps
+    /// growing the same leaf grows it by all the remaining events at once.
+    fn event_n_in_place<S: GrowthStrategy<C>>(
+        &mut self,
+        id: &IdTree,
+        n: C,
+        policy: OverflowPolicy,
+        strategy: &S,
+    ) -> Result<(), EventError> {
+        if id.is_anonymous() {
+            return Err(EventError::AnonymousId);
+        }
+        if n == C::ZERO {
+            return Ok(());
+        }
+
+        // Growing can only fail after earlier events succeeded when there is more than one
+        let mut backup = (n > C::ONE && policy == OverflowPolicy::Error).then(|| self.clone());
+        let saturate = policy == OverflowPolicy::Saturate;
+        let mut result = Ok(());
+        let mut n = n;
+        while n > C::ZERO {
+            if self.fill_in_place(id) {
+                n -= C::ONE;
+                continue;
+            }
+
+            let grown = if strategy.grows_same_leaf() {
+                n
+            } else {
+                C::ONE
+            };
+            let depth = self.depth();
+            if let Err(e) = self.grow_in_place(id, grown, saturate, depth, strategy) {
+                match backup.take() {
+                    Some(backup) => std::mem::replace(self, backup).drop_iteratively(),
+                    // Growing may have expanded leaves on the way down before failing
+                    None => self.normalize_in_place(),
+                }
+                if policy == OverflowPolicy::Error {
+                    result = Err(e.into());
+                }
+                break;
+            }
+            n -= grown;
+        }
+        if let Some(backup) = backup {
+            backup.drop_iteratively();
+        }
+        result
+    }
+
+    /// Checks that every counter, once the values along its path are summed, fits in the counter
+    /// type. Trees received from untrusted peers should be validated before use.
+    pub fn validate(&self) -> Result<(), OverflowError> {
+        use EventTree::*;
+        let mut stack = vec![(self, C::ZERO)];
+        while let Some((e, base)) = stack.pop() {
+            match e {
+                Leaf(val) => {
+                    base.checked_add(*val).ok_or(OverflowError)?;
+                }
+                SubTree(val, l, r) => {
+                    let base = base.checked_add(*val).ok_or(OverflowError)?;
+                    stack.push((r, base));
+                    stack.push((l, base));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the tree according to `policy`, clamping overflowing counters when saturating.
+    pub fn sanitize(mut self, policy: OverflowPolicy) -> Result<Self, OverflowError> {
+        match policy {
+            OverflowPolicy::Error => self.validate().map(|_| self),
+            OverflowPolicy::Saturate => {
+                self.saturate();
+                Ok(self)
+            }
+        }
+    }
+
+    fn saturate(&mut self) {
+        use EventTree::*;
+
+        enum Frame<C: Counter> {
+            Visit(EventTree<C>, C),
+            Node(EventTree<C>),
+        }
+
+        let mut frames = vec![Frame::Visit(std::mem::take(self), C::ZERO)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(Leaf(val), base) => done.push(Leaf(val.min(C::MAX - base))),
+                Frame::Visit(e @ SubTree(..), base) if base.checked_add(e.value()).is_none() => {
+                    e.drop_iteratively();
+                    done.push(Leaf(C::MAX - base));
+                }
+                Frame::Visit(mut e, base) => {
+                    let base = base.saturating_add(e.value());
+                    let (l, r) = e.take_children();
+                    frames.push(Frame::Node(e));
+                    frames.push(Frame::Visit(r, base));
+                    frames.push(Frame::Visit(l, base));
+                }
+                Frame::Node(mut e) => {
+                    let (l, r) = Self::pop_pair(&mut done);
+                    e.put_children(l, r);
+                    e.norm_root();
+                    done.push(e);
+                }
+            }
+        }
+        *self = done.pop().expect("The root is always saturated");
+    }
+
+    /// Saturating substraction of the other EventTree
+    pub fn diff(&self, other: &Self) -> Self {
+        use EventTree::*;
+        Self::zip_with(self, other, |a, a_base, b, b_base| match (a, b) {
+            (Leaf(x), Leaf(y)) => Zip::Done(Leaf(
+                a_base
+                    .saturating_add(*x)
+                    .saturating_sub(b_base.saturating_add(*y)),
+            )),
+            (Leaf(x), SubTree(y, _, _))
+                if a_base.saturating_add(*x) <= b_base.saturating_add(*y) =>
+            {
+                Zip::Done(Leaf(C::ZERO))
+            }
+            (SubTree(x, _, _), Leaf(y))
+                if b_base.saturating_add(*y) <= a_base.saturating_add(*x) =>
+            {
+                Zip::Done(a.lifted(a_base).sink(b_base.saturating_add(*y)))
+            }
+            _ => Zip::Split,
+        })
+    }
+
+    /// Computes the causal relation to `other` in a single traversal of both trees.
+    pub fn relation(&self, other: &Self) -> Relation {
+        use EventTree::*;
+
+        // Cleared whenever a point is found where `self` is greater or lesser than `other`
+        let mut le = true;
+        let mut ge = true;
+
+        let mut stack = vec![(self, C::ZERO, other, C::ZERO)];
+        while let Some((a, a_base, b, b_base)) = stack.pop() {
+            if let (Leaf(x), Leaf(y)) = (a, b) {
+                match a_base.saturating_add(*x).cmp(&b_base.saturating_add(*y)) {
+                    Ordering::Less => ge = false,
+                    Ordering::Greater => le = false,
+                    Ordering::Equal => {}
+                }
+                if !le && !ge {
+                    break;
+                }
+            } else {
+                let [left, right] = Self::split(a, a_base, b, b_base);
+                stack.push(right);
+                stack.push(left);
+            }
+        }
+
+        match (le, ge) {
+            (true, true) => Relation::Equal,
+            (true, false) => Relation::HappenedBefore,
+            (false, true) => Relation::HappenedAfter,
+            (false, false) => Relation::Concurrent,
+        }
+    }
+
+    /// A total order extending the causal one, comparing the trees point by point from the left
+    /// of the interval, so a tree which happened before another always sorts first.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
         use EventTree::*;
-        match (self, other) {
-            (Leaf(a), Leaf(b)) => Leaf(a.saturating_sub(*b)),
-            (a @ SubTree(..), Leaf(b)) => a.diff(&Self::subtree(0, Leaf(*b), Leaf(*b))).norm(),
-            (Leaf(a), b @ SubTree(..)) => Self::subtree(0, Leaf(a), Leaf(a)).diff(b).norm(),
-            (SubTree(a, l0, r0), SubTree(b, l1, r1)) => Self::subtree(
-                0,
-                l0.lift(a).diff(&l1.clone().lift(*b)),
-                r0.lift(a).diff(&r1.clone().lift(*b)),
-            )
-            .norm(),
+        let mut stack = vec![(self, C::ZERO, other, C::ZERO)];
+        while let Some((a, a_base, b, b_base)) = stack.pop() {
+            if let (Leaf(x), Leaf(y)) = (a, b) {
+                match a_base.saturating_add(*x).cmp(&b_base.saturating_add(*y)) {
+                    Ordering::Equal => {}
+                    ordering => return ordering,
+                }
+            } else {
+                let [left, right] = Self::split(a, a_base, b, b_base);
+                stack.push(right);
+                stack.push(left);
+            }
         }
+        Ordering::Equal
     }
 
+    /// Whether any non-zero value may lie within the region owned by `id`.
+    ///
+    /// This is exact for the ids `0` and `1`. For any other id, a subtree counts as holding values
+    /// within the region without looking at where they actually are.
     pub fn contains(&self, id: &IdTree) -> bool {
-        match (self, id) {
-            (EventTree::Leaf(0), _) | (_, IdTree::Zero) => false,
-            (EventTree::Leaf(_), _) => true,
-            (EventTree::SubTree(0, l, r), id @ IdTree::One) => l.contains(id) || r.contains(id),
-            (EventTree::SubTree(_, _, _), _) => true,
+        use EventTree::*;
+        let mut stack = vec![(self, id)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (Leaf(val), _) if *val == C::ZERO => {}
+                (_, IdTree::Zero) => {}
+                (SubTree(val, l, r), IdTree::One) if *val == C::ZERO => {
+                    stack.push((r, id));
+                    stack.push((l, id));
+                }
+                _ => return true,
+            }
         }
+        false
     }
 
     /// Returns an EventTree with values only where `other` is non-zero.
     pub fn mask(&self, other: &Self) -> Self {
         use EventTree::*;
-        match (self, other) {
-            (_, Leaf(0)) => Leaf(0),
-            (Leaf(a), Leaf(_)) => Leaf(*a),
-            (a @ SubTree(..), Leaf(_)) => a.clone(),
-            (Leaf(a), b @ SubTree(..)) => Self::subtree(0, Leaf(*a), Leaf(*a)).mask(b).norm(),
-            (SubTree(a, l0, r0), SubTree(0, l1, r1)) => Self::subtree(
-                0,
-                l0.clone().lift(*a).mask(l1),
-                r0.clone().lift(*a).mask(r1),
-            )
-            .norm(),
-            (a @ SubTree(..), SubTree(..)) => a.clone(),
-        }
+        // Splitting only continues below zero-valued nodes of the mask, so its base stays zero.
+        Self::zip_with(self, other, |a, a_base, mask, _| match mask {
+            Leaf(m) if *m == C::ZERO => Zip::Done(Leaf(C::ZERO)),
+            Leaf(_) => Zip::Done(a.lifted(a_base)),
+            SubTree(m, _, _) if *m > C::ZERO => Zip::Done(a.lifted(a_base)),
+            SubTree(..) => Zip::Split,
+        })
+    }
+
+    /// Returns an EventTree with values only within the region owned by `id`.
+    pub fn restrict(&self, id: &IdTree) -> Self {
+        self.project(id, true)
+    }
+
+    /// Returns an EventTree with values only outside the region owned by `id`.
+    pub fn exclude(&self, id: &IdTree) -> Self {
+        self.project(id, false)
     }
 
-    fn norm(&self) -> Self {
+    /// Keeps the values either inside or outside the region owned by `id`, zeroing the rest.
+    fn project(&self, id: &IdTree, inside: bool) -> Self {
         use EventTree::*;
-        match self {
-            Leaf(_) => self.clone(),
-            SubTree(val, l, r) => {
-                let l = l.norm();
-                let r = r.norm();
-                if matches!((&l, &r), (Leaf(m0), Leaf(m1)) if m0 == m1) {
-                    Leaf(val + l.value())
+
+        let mut frames = vec![Some((self, C::ZERO, id))];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some((e, base, id)) = frame else {
+                let (l, r) = Self::pop_pair(&mut done);
+                done.push(Self::node(C::ZERO, l, r));
+                continue;
+            };
+
+            match id {
+                IdTree::Zero | IdTree::One => done.push(if matches!(id, IdTree::One) == inside {
+                    e.lifted(base)
                 } else {
-                    let m = l.value().min(r.value());
-                    SubTree(val + m, Box::new(l.sink(m)), Box::new(r.sink(m)))
+                    Leaf(C::ZERO)
+                }),
+                IdTree::SubTree(il, ir) => {
+                    let (l, r, base) = match e {
+                        Leaf(_) => (e, e, base),
+                        SubTree(val, l, r) => (l.as_ref(), r.as_ref(), base.saturating_add(*val)),
+                    };
+                    frames.push(None);
+                    frames.push(Some((r, base, ir)));
+                    frames.push(Some((l, base, il)));
+                }
+            }
+        }
+        done.pop().expect("The root is always built")
+    }
+
+    /// Walks `a` and `b` together, carrying the accumulated base of each side. Wherever `visit`
+    /// splits, the results for the children are combined under a zero-valued node.
+    fn zip_with<'a, F>(a: &'a Self, b: &'a Self, visit: F) -> Self
+    where
+        F: Fn(&'a Self, C, &'a Self, C) -> Zip<C>,
+    {
+        let mut frames = vec![Some((a, C::ZERO, b, C::ZERO))];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some((a, a_base, b, b_base)) = frame else {
+                let (l, r) = Self::pop_pair(&mut done);
+                done.push(Self::node(C::ZERO, l, r));
+                continue;
+            };
+
+            match visit(a, a_base, b, b_base) {
+                Zip::Done(e) => done.push(e),
+                Zip::Split => {
+                    let [left, right] = Self::split(a, a_base, b, b_base);
+                    frames.push(None);
+                    frames.push(Some(right));
+                    frames.push(Some(left));
                 }
             }
         }
+        done.pop().expect("The root is always built")
     }
 
-    fn value(&self) -> u64 {
+    /// Pairs up the children of `a` and `b` along with their bases. A leaf stands in for both of
+    /// its own children.
+    fn split<'a>(
+        a: &'a Self,
+        a_base: C,
+        b: &'a Self,
+        b_base: C,
+    ) -> [(&'a Self, C, &'a Self, C); 2] {
         use EventTree::*;
-        match self {
-            Leaf(val) => *val,
-            SubTree(val, _, _) => *val,
+        let (al, ar, a_base) = match a {
+            Leaf(_) => (a, a, a_base),
+            SubTree(x, l, r) => (l.as_ref(), r.as_ref(), a_base.saturating_add(*x)),
+        };
+        let (bl, br, b_base) = match b {
+            Leaf(_) => (b, b, b_base),
+            SubTree(y, l, r) => (l.as_ref(), r.as_ref(), b_base.saturating_add(*y)),
+        };
+        [(al, a_base, bl, b_base), (ar, a_base, br, b_base)]
+    }
+
+    /// Brings the tree into its canonical form: every subtree has a child with value zero and no
+    /// subtree has two equal leaves.
+    pub fn normalize(mut self) -> Self {
+        self.normalize_in_place();
+        self
+    }
+
+    fn normalize_in_place(&mut self) {
+        if self.is_normalized() {
+            return;
         }
+
+        let mut frames = vec![Some(std::mem::take(self))];
+        let mut parents = vec![];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(mut e @ EventTree::SubTree(..)) => {
+                    let (l, r) = e.take_children();
+                    parents.push(e);
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                Some(leaf) => done.push(leaf),
+                None => {
+                    let mut e = parents.pop().expect("Every node frame has a parent");
+                    let (l, r) = Self::pop_pair(&mut done);
+                    e.put_children(l, r);
+                    e.norm_root();
+                    done.push(e);
+                }
+            }
+        }
+        *self = done.pop().expect("The root is always normalized");
     }
 
-    fn depth(&self, at: u64) -> u64 {
+    pub fn is_normalized(&self) -> bool {
         use EventTree::*;
-        match self {
-            Leaf(_) => at + 1,
-            SubTree(_, l, r) => {
-                let at = at + 1;
-                l.depth(at).max(r.depth(at))
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            if let SubTree(_, l, r) = e {
+                match (l.as_ref(), r.as_ref()) {
+                    (Leaf(m0), Leaf(m1)) if m0 == m1 => return false,
+                    _ if l.value().min(r.value()) != C::ZERO => return false,
+                    _ => {
+                        stack.push(r);
+                        stack.push(l);
+                    }
+                }
             }
         }
+        true
     }
 
-    fn lift(self, m: u64) -> Self {
+    fn hash_normalized<H: std::hash::Hasher>(&self, state: &mut H) {
         use EventTree::*;
+        use std::hash::Hash;
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            match e {
+                Leaf(val) => (0u8, val).hash(state),
+                SubTree(val, l, r) => {
+                    (1u8, val).hash(state);
+                    stack.push(r);
+                    stack.push(l);
+                }
+            }
+        }
+    }
+
+    /// Builds a node over already normalized children, hoisting their common minimum.
+    fn node(val: C, l: Self, r: Self) -> Self {
+        use EventTree::*;
+        match (&l, &r) {
+            (Leaf(m0), Leaf(m1)) if m0 == m1 => Leaf(val.saturating_add(*m0)),
+            _ => {
+                let m = l.value().min(r.value());
+                SubTree(
+                    val.saturating_add(m),
+                    Box::new(l.sink(m)),
+                    Box::new(r.sink(m)),
+                )
+            }
+        }
+    }
+
+    /// Moves the children out of a subtree, leaving zero leaves in their place so the node's
+    /// allocations can be reused by [`EventTree::put_children`].
+    fn take_children(&mut self) -> (Self, Self) {
         match self {
-            Leaf(val) => Leaf(val + m),
-            SubTree(val, l, r) => SubTree(val + m, l, r),
+            EventTree::SubTree(_, l, r) => (std::mem::take(&mut **l), std::mem::take(&mut **r)),
+            EventTree::Leaf(_) => unreachable!("Only subtrees have children"),
+        }
+    }
+
+    fn put_children(&mut self, left: Self, right: Self) {
+        if let EventTree::SubTree(_, l, r) = self {
+            **l = left;
+            **r = right;
         }
     }
 
-    fn sink(self, m: u64) -> Self {
+    /// Pops the two most recently built trees, in the order they were pushed.
+    fn pop_pair(done: &mut Vec<Self>) -> (Self, Self) {
+        let r = done.pop().expect("Right child is built last");
+        let l = done.pop().expect("Left child is built first");
+        (l, r)
+    }
+
+    /// Clones the tree with its root lifted by `m`.
+    fn lifted(&self, m: C) -> Self {
         use EventTree::*;
         match self {
-            Leaf(val) => Leaf(val - m),
-            SubTree(val, l, r) => SubTree(val - m, l, r),
+            Leaf(val) => Leaf(val.saturating_add(m)),
+            SubTree(val, l, r) => SubTree(val.saturating_add(m), l.clone(), r.clone()),
         }
     }
 
-    fn min(&self) -> u64 {
+    fn lift_root(&mut self, m: C) {
+        let val = self.value_mut();
+        *val = val.saturating_add(m);
+    }
+
+    fn value_mut(&mut self) -> &mut C {
         use EventTree::*;
         match self {
-            Leaf(val) => *val,
-            SubTree(val, _, _) => *val,
+            Leaf(val) => val,
+            SubTree(val, _, _) => val,
         }
     }
 
-    fn max(&self) -> u64 {
+    /// Normalizes the root assuming both children are already normalized.
+    fn norm_root(&mut self) {
+        use EventTree::*;
+        if let SubTree(val, l, r) = self {
+            match (l.as_ref(), r.as_ref()) {
+                (Leaf(m0), Leaf(m1)) if m0 == m1 => *self = Leaf(val.saturating_add(*m0)),
+                _ => {
+                    let m = l.value().min(r.value());
+                    *val = val.saturating_add(m);
+                    *l.value_mut() -= m;
+                    *r.value_mut() -= m;
+                }
+            }
+        }
+    }
+
+    fn value(&self) -> C {
         use EventTree::*;
         match self {
             Leaf(val) => *val,
-            SubTree(val, l, r) => val + l.max().max(r.max()),
-        }
-    }
-
-    fn fill(&self, id: &IdTree) -> Self {
-        match (id, self) {
-            (IdTree::Zero, e) => e.clone(),
-            (IdTree::One, e) => EventTree::Leaf(e.max()),
-            (_, n @ EventTree::Leaf(_)) => n.clone(),
-            (IdTree::SubTree(il, ir), EventTree::SubTree(n, el, er)) => {
-                let il: &IdTree = il;
-                let ir: &IdTree = ir;
-                match (il, ir) {
-                    (&IdTree::One, ir) => {
-                        let er = er.fill(ir);
-                        EventTree::SubTree(
-                            *n,
-                            Box::new(EventTree::Leaf(el.max().max(er.min()))),
-                            Box::new(er),
-                        )
-                        .norm()
-                    }
-                    (il, &IdTree::One) => {
-                        let el = el.fill(il);
-                        EventTree::SubTree(
-                            *n,
-                            Box::new(el.clone()),
-                            Box::new(EventTree::Leaf(er.max().max(el.min()))),
-                        )
-                        .norm()
+            SubTree(val, _, _) => *val,
+        }
+    }
+
+    fn depth(&self) -> u64 {
+        use EventTree::*;
+        let mut depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((e, at)) = stack.pop() {
+            depth = depth.max(at);
+            if let SubTree(_, l, r) = e {
+                stack.push((r, at + 1));
+                stack.push((l, at + 1));
+            }
+        }
+        depth
+    }
+
+    fn sink(mut self, m: C) -> Self {
+        *self.value_mut() -= m;
+        self
+    }
+
+    fn min(&self) -> C {
+        self.value()
+    }
+
+    fn max(&self) -> C {
+        use EventTree::*;
+        let mut max = C::ZERO;
+        let mut stack = vec![(self, C::ZERO)];
+        while let Some((e, base)) = stack.pop() {
+            match e {
+                Leaf(val) => max = max.max(base.saturating_add(*val)),
+                SubTree(val, l, r) => {
+                    let base = base.saturating_add(*val);
+                    stack.push((r, base));
+                    stack.push((l, base));
+                }
+            }
+        }
+        max
+    }
+
+    /// Fills in place, returning whether anything changed.
+    fn fill_in_place(&mut self, id: &IdTree) -> bool {
+        use EventTree::*;
+
+        enum Frame<'a, C: Counter> {
+            Visit(EventTree<C>, &'a IdTree),
+            /// A child which is left as is until its parent is rebuilt.
+            Keep(EventTree<C>),
+            Node(EventTree<C>, &'a IdTree),
+        }
+
+        let mut frames = vec![Frame::Visit(std::mem::take(self), id)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(e, IdTree::Zero) | Frame::Visit(e @ Leaf(_), _) | Frame::Keep(e) => {
+                    done.push((e, false))
+                }
+                Frame::Visit(e, IdTree::One) => {
+                    let max = e.max();
+                    e.drop_iteratively();
+                    done.push((Leaf(max), true));
+                }
+                Frame::Visit(mut e, id @ IdTree::SubTree(il, ir)) => {
+                    let (el, er) = e.take_children();
+                    frames.push(Frame::Node(e, id));
+                    match (il.as_ref(), ir.as_ref()) {
+                        (IdTree::One, ir) => {
+                            frames.push(Frame::Visit(er, ir));
+                            frames.push(Frame::Keep(el));
+                        }
+                        (il, IdTree::One) => {
+                            frames.push(Frame::Keep(er));
+                            frames.push(Frame::Visit(el, il));
+                        }
+                        (il, ir) => {
+                            frames.push(Frame::Visit(er, ir));
+                            frames.push(Frame::Visit(el, il));
+                        }
                     }
-                    (il, ir) => {
-                        EventTree::SubTree(*n, Box::new(el.fill(il)), Box::new(er.fill(ir))).norm()
+                }
+                Frame::Node(mut e, id) => {
+                    let (mut r, r_changed) = done.pop().expect("Right child is filled last");
+                    let (mut l, l_changed) = done.pop().expect("Left child is filled first");
+                    let changed = match id {
+                        IdTree::SubTree(il, _) if matches!(il.as_ref(), IdTree::One) => {
+                            l.set_leaf(l.max().max(r.min())) | r_changed
+                        }
+                        IdTree::SubTree(_, ir) if matches!(ir.as_ref(), IdTree::One) => {
+                            r.set_leaf(r.max().max(l.min())) | l_changed
+                        }
+                        _ => l_changed | r_changed,
+                    };
+
+                    e.put_children(l, r);
+                    if changed {
+                        e.norm_root();
                     }
+                    done.push((e, changed));
                 }
             }
         }
+
+        let (e, changed) = done.pop().expect("The root is always filled");
+        *self = e;
+        changed
     }
 
-    #[allow(non_snake_case)]
-    fn grow(&self, id: &IdTree, N: u64) -> (Self, u64) {
-        match (id, self) {
-            (IdTree::One, EventTree::Leaf(val)) => (EventTree::Leaf(val + 1), 0),
-            (_, EventTree::Leaf(val)) => {
-                let (e, c) = EventTree::SubTree(
-                    *val,
-                    Box::new(EventTree::Leaf(0)),
-                    Box::new(EventTree::Leaf(0)),
-                )
-                .grow(id, N);
-                (e, c + N)
-            }
-            (IdTree::SubTree(il, ir), EventTree::SubTree(n, el, er)) => {
-                let il: &IdTree = il;
-                let ir: &IdTree = ir;
-                match (il, ir) {
-                    (&IdTree::Zero, ir) => {
-                        let (er, c) = er.grow(ir, N);
-                        (EventTree::SubTree(*n, el.clone(), Box::new(er)), c + 1)
-                    }
-                    (il, &IdTree::Zero) => {
-                        let (el, c) = el.grow(il, N);
-                        (EventTree::SubTree(*n, Box::new(el), er.clone()), c + 1)
+    /// Replaces the tree with `Leaf(val)`, returning whether that was a change.
+    fn set_leaf(&mut self, val: C) -> bool {
+        if matches!(self, EventTree::Leaf(v) if *v == val) {
+            false
+        } else {
+            std::mem::replace(self, EventTree::Leaf(val)).drop_iteratively();
+            true
+        }
+    }
+
+    /// Grows the tree under `id` by walking down to the leaf `strategy` finds cheapest to
+    /// increment, and adds `n` to it. When `saturate` is set the leaf is raised as far as it can
+    /// go instead of failing, unless it is already at the maximum.
+    fn grow_in_place<S: GrowthStrategy<C>>(
+        &mut self,
+        id: &IdTree,
+        n: C,
+        saturate: bool,
+        depth: u64,
+        strategy: &S,
+    ) -> Result<(), OverflowError> {
+        use EventTree::*;
+        let costs = self.grow_costs(id, depth, strategy);
+        let mut node = self;
+        let mut id = id;
+        // The position of the pair of `node` and `id` in `costs`
+        let mut at = 0;
+        // The sum of the values above `node`
+        let mut base = C::ZERO;
+        loop {
+            if let (IdTree::SubTree(..), Leaf(val)) = (id, &*node) {
+                let val = *val;
+                *node = SubTree(val, Box::new(Leaf(C::ZERO)), Box::new(Leaf(C::ZERO)));
+            }
+
+            match (id, node) {
+                (IdTree::One, Leaf(val)) => {
+                    let room = C::MAX.saturating_sub(base).saturating_sub(*val);
+                    if n <= room {
+                        *val = val.saturating_add(n);
+                    } else if saturate && room > C::ZERO {
+                        *val = val.saturating_add(room);
+                    } else {
+                        return Err(OverflowError);
                     }
-                    (il, ir) => {
-                        let (erg, cr) = er.grow(ir, N);
-                        let (elg, cl) = el.grow(il, N);
-                        if cl < cr {
-                            (EventTree::SubTree(*n, Box::new(elg), er.clone()), cl + 1)
-                        } else {
-                            (EventTree::SubTree(*n, el.clone(), Box::new(erg)), cr + 1)
+                    return Ok(());
+                }
+                (IdTree::SubTree(il, ir), SubTree(val, el, er)) => {
+                    base = base.saturating_add(*val);
+                    let (l, r) = (at + 1, at + 1 + costs[at + 1].1);
+                    let left = match (il.as_ref(), ir.as_ref()) {
+                        (IdTree::Zero, _) => false,
+                        (_, IdTree::Zero) => true,
+                        _ => costs[l].0 < costs[r].0,
+                    };
+                    (id, node, at) = if left {
+                        (il, el.as_mut(), l)
+                    } else {
+                        (ir, er.as_mut(), r)
+                    };
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Prices growing the tree for `id` under `strategy` in a single pass, where `depth` is the
+    /// depth of the whole tree. Every pair of node and id visited gets an entry in preorder,
+    /// holding the cost of the cheapest growth below it and the number of entries it spans.
+    fn grow_costs<S: GrowthStrategy<C>>(
+        &self,
+        id: &IdTree,
+        depth: u64,
+        strategy: &S,
+    ) -> Vec<(u64, usize)> {
+        use EventTree::*;
+
+        enum Frame<'a, C: Counter> {
+            Visit(&'a IdTree, &'a EventTree<C>),
+            /// Take the cheaper of the two children of the entry, then add.
+            Min(usize, u64),
+        }
+
+        // The children a leaf is expanded into
+        let zero = Leaf(C::ZERO);
+        let mut frames = vec![Frame::Visit(id, self)];
+        let mut costs: Vec<(u64, usize)> = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                // Never grow into a region we do not own
+                Frame::Visit(IdTree::Zero, _) => costs.push((u64::MAX, 1)),
+                Frame::Visit(IdTree::One, Leaf(val)) => {
+                    costs.push((strategy.increment_cost(*val), 1))
+                }
+                Frame::Visit(IdTree::SubTree(il, ir), e) => {
+                    // A leaf has to be expanded first
+                    let (el, er, extra) = match e {
+                        Leaf(val) => (&zero, &zero, strategy.step_cost(Some(*val), depth)),
+                        SubTree(_, el, er) => {
+                            (el.as_ref(), er.as_ref(), strategy.step_cost(None, depth))
                         }
-                    }
+                    };
+                    frames.push(Frame::Min(costs.len(), extra));
+                    costs.push((0, 0));
+                    frames.push(Frame::Visit(ir, er));
+                    frames.push(Frame::Visit(il, el));
+                }
+                Frame::Visit(IdTree::One, SubTree(..)) => unreachable!(),
+                Frame::Min(at, extra) => {
+                    let l = costs[at + 1].0;
+                    let r = costs[at + 1 + costs[at + 1].1].0;
+                    costs[at] = (l.min(r).saturating_add(extra), costs.len() - at);
                 }
             }
-            _ => unreachable!(),
         }
+        costs
     }
 }
 
-impl Default for EventTree {
+/// Which bound [`EventTree::zip_all`] computes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Upper,
+    Lower,
+}
+
+/// What [`EventTree::zip_with`] does with a pair of nodes.
+enum Zip<C: Counter> {
+    Done(EventTree<C>),
+    Split,
+}
+
+impl<C: Counter> Default for EventTree<C> {
     fn default() -> Self {
-        EventTree::Leaf(0)
+        EventTree::Leaf(C::ZERO)
     }
 }
 
-impl PartialOrd for EventTree {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+impl<C: Counter> Clone for EventTree<C> {
+    fn clone(&self) -> Self {
         use EventTree::*;
-        match (self, other) {
-            (Leaf(a), Leaf(b)) => Some(a.cmp(b)),
-            (Leaf(a), SubTree(b, l, r)) => {
-                // TODO: Is there a way to avoid these clones? Ditto below.
-                let l_cmp = Leaf(*a).partial_cmp(&l.clone().lift(*b))?;
-                let r_cmp = Leaf(*a).partial_cmp(&r.clone().lift(*b))?;
-                match (l_cmp, r_cmp) {
-                    (Ordering::Greater, Ordering::Greater) => Some(Ordering::Greater),
-                    (Ordering::Less, Ordering::Less) => Some(Ordering::Less),
-                    (Ordering::Equal, x) | (x, Ordering::Equal) => Some(x),
-                    (Ordering::Less, Ordering::Greater) | (Ordering::Greater, Ordering::Less) => {
-                        None
-                    }
+        let mut frames = vec![Some(self)];
+        let mut values = vec![];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(Leaf(val)) => done.push(Leaf(*val)),
+                Some(SubTree(val, l, r)) => {
+                    values.push(*val);
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
                 }
-            }
-            (SubTree(a, l, r), Leaf(b)) => {
-                let l_cmp = l.clone().lift(*a).partial_cmp(&Leaf(*b))?;
-                let r_cmp = r.clone().lift(*a).partial_cmp(&Leaf(*b))?;
-                match (l_cmp, r_cmp) {
-                    (Ordering::Greater, Ordering::Greater) => Some(Ordering::Greater),
-                    (Ordering::Less, Ordering::Less) => Some(Ordering::Less),
-                    (Ordering::Equal, x) | (x, Ordering::Equal) => Some(x),
-                    (Ordering::Less, Ordering::Greater) | (Ordering::Greater, Ordering::Less) => {
-                        None
-                    }
+                None => {
+                    let val = values.pop().expect("Every node frame has a value");
+                    let (l, r) = Self::pop_pair(&mut done);
+                    done.push(SubTree(val, Box::new(l), Box::new(r)));
                 }
             }
-            (SubTree(a, l0, r0), SubTree(b, l1, r1)) => {
-                let l_cmp = l0.clone().lift(*a).partial_cmp(&l1.clone().lift(*b))?;
-                let r_cmp = r0.clone().lift(*a).partial_cmp(&r1.clone().lift(*b))?;
-                match (l_cmp, r_cmp) {
-                    (Ordering::Greater, Ordering::Greater) => Some(Ordering::Greater),
-                    (Ordering::Less, Ordering::Less) => Some(Ordering::Less),
-                    (Ordering::Equal, x) | (x, Ordering::Equal) => Some(x),
-                    (Ordering::Less, Ordering::Greater) | (Ordering::Greater, Ordering::Less) => {
-                        None
-                    }
+        }
+        done.pop().expect("The root is always cloned")
+    }
+}
+
+impl<C: Counter> EventTree<C> {
+    /// Drops the tree one node at a time. Dropping a tree as usual recurses once per level, which
+    /// can overflow the stack for trees many thousands of levels deep.
+    pub fn drop_iteratively(mut self) {
+        let mut stack = vec![];
+        self.detach_subtrees(&mut stack);
+        while let Some(mut e) = stack.pop() {
+            e.detach_subtrees(&mut stack);
+        }
+    }
+
+    fn detach_subtrees(&mut self, stack: &mut Vec<Self>) {
+        if let EventTree::SubTree(_, l, r) = self {
+            for child in [l, r] {
+                if matches!(**child, EventTree::SubTree(..)) {
+                    stack.push(std::mem::take(&mut **child));
                 }
             }
         }
     }
 }
 
-impl PartialEq for EventTree {
+impl<C: Counter> PartialOrd for EventTree<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.relation(other).into()
+    }
+}
+
+/// An [`EventTree`] ordered by [`EventTree::total_cmp`] rather than causally, so that timestamps
+/// can be kept in ordered collections such as a `BTreeMap`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TotalOrd<C: Counter = u64>(pub EventTree<C>);
+
+impl<C: Counter> From<EventTree<C>> for TotalOrd<C> {
+    fn from(event: EventTree<C>) -> Self {
+        Self(event)
+    }
+}
+
+impl<C: Counter> PartialOrd for TotalOrd<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C: Counter> Ord for TotalOrd<C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Timestamps are equal when they have seen the same events, regardless of their shape.
+impl<C: Counter> PartialEq for EventTree<C> {
     fn eq(&self, other: &Self) -> bool {
-        use EventTree::*;
-        match (self, other) {
-            (Leaf(a), Leaf(b)) if a == b => true,
-            (Leaf(_), Leaf(_)) => false,
-            (SubTree(a, l0, r0), SubTree(b, l1, r1)) if a == b => l0.eq(l1) && r0.eq(r1),
-            _ => false,
-        }
+        self.relation(other) == Relation::Equal
     }
 }
 
-impl Eq for EventTree {}
+impl<C: Counter> Eq for EventTree<C> {}
 
-impl std::fmt::Display for EventTree {
+// Equality compares saturated sums, so trees which overflow are hashed once saturated, which also
+// normalizes them
+impl<C: Counter> std::hash::Hash for EventTree<C> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        if self.is_normalized() && self.validate().is_ok() {
+            self.hash_normalized(state);
+        } else {
+            let mut saturated = self.clone();
+            saturated.saturate();
+            saturated.hash_normalized(state);
+            saturated.drop_iteratively();
+        }
+    }
+}
+
+impl<C: Counter> std::fmt::Display for EventTree<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         use EventTree::*;
-        match self {
-            Leaf(val) => write!(f, "{}", val),
-            SubTree(val, l, r) => write!(f, "({}, {}, {})", val, l, r),
+
+        enum Token<'a, C: Counter> {
+            Event(&'a EventTree<C>),
+            Text(&'static str),
         }
+
+        let mut stack = vec![Token::Event(self)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Event(Leaf(val)) => write!(f, "{val}")?,
+                Token::Event(SubTree(val, l, r)) => {
+                    write!(f, "({val}, ")?;
+                    stack.push(Token::Text(")"));
+                    stack.push(Token::Event(r));
+                    stack.push(Token::Text(", "));
+                    stack.push(Token::Event(l));
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -328,7 +1290,7 @@ mod tests {
     fn test_joins_1() {
         use EventTree::*;
 
-        let e0 = SubTree(3, Box::new(Leaf(3)), Box::new(Leaf(0)));
+        let e0: EventTree = SubTree(3, Box::new(Leaf(3)), Box::new(Leaf(0)));
         let e1 = SubTree(3, Box::new(Leaf(0)), Box::new(Leaf(4)));
 
         let e2 = e0.join(e1);
@@ -339,7 +1301,7 @@ mod tests {
     fn test_joins_2() {
         use EventTree::*;
 
-        let e0 = SubTree(3, Box::new(Leaf(3)), Box::new(Leaf(0)));
+        let e0: EventTree = SubTree(3, Box::new(Leaf(3)), Box::new(Leaf(0)));
         let e1 = SubTree(0, Box::new(Leaf(0)), Box::new(Leaf(4)));
 
         let e2 = e0.join(e1);
@@ -350,7 +1312,7 @@ mod tests {
     fn test_larger_leaf() {
         use EventTree::*;
 
-        let e0 = SubTree(0, Box::new(Leaf(0)), Box::new(Leaf(1)));
+        let e0: EventTree = SubTree(0, Box::new(Leaf(0)), Box::new(Leaf(1)));
         let e1 = Leaf(1);
 
         let e2 = e0.join(e1);
@@ -359,7 +1321,7 @@ mod tests {
 
     #[test]
     fn test_ordering_1() {
-        let e0 = EventTree::Leaf(3);
+        let e0: EventTree = EventTree::Leaf(3);
         let e1 = EventTree::SubTree(
             2,
             Box::new(EventTree::Leaf(1)),
@@ -371,8 +1333,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::neg_cmp_op_on_partial_ord)]
     fn test_ordering_2() {
-        let e0 = EventTree::SubTree(
+        let e0: EventTree = EventTree::SubTree(
             1,
             Box::new(EventTree::Leaf(3)),
             Box::new(EventTree::Leaf(0)),
@@ -395,9 +1358,40 @@ mod tests {
         assert!(!(e1 <= e0));
     }
 
+    #[test]
+    fn test_relation() {
+        use EventTree::*;
+
+        let e0: EventTree = EventTree::subtree(1, Leaf(3), Leaf(0));
+        let e1 = EventTree::subtree(2, Leaf(1), Leaf(4));
+        let e2 = EventTree::subtree(2, Leaf(2), Leaf(4));
+
+        assert_eq!(e0.relation(&e1), Relation::Concurrent);
+        assert_eq!(e1.relation(&e0), Relation::Concurrent);
+        assert_eq!(e1.relation(&e2), Relation::HappenedBefore);
+        assert_eq!(e2.relation(&e1), Relation::HappenedAfter);
+        assert_eq!(e2.relation(&e2), Relation::Equal);
+
+        assert_eq!(Leaf(3).relation(&e0), Relation::Concurrent);
+        assert_eq!(Leaf(4).relation(&e0), Relation::HappenedAfter);
+        assert_eq!(Leaf(1).relation(&e0), Relation::HappenedBefore);
+    }
+
+    #[test]
+    fn test_relation_unnormalized() {
+        use EventTree::*;
+
+        let e0: EventTree = EventTree::SubTree(1, Box::new(Leaf(2)), Box::new(Leaf(2)));
+        let e1 = EventTree::SubTree(0, Box::new(Leaf(3)), Box::new(Leaf(3)));
+
+        assert_eq!(e0.relation(&e1), Relation::Equal);
+        assert_eq!(e0.relation(&Leaf(3)), Relation::Equal);
+        assert_eq!(e0.partial_cmp(&e1), Some(Ordering::Equal));
+    }
+
     #[test]
     fn test_diff_1() {
-        let e0 = EventTree::Leaf(5);
+        let e0: EventTree = EventTree::Leaf(5);
         let e1 = EventTree::subtree(4, EventTree::Leaf(2), EventTree::Leaf(0));
 
         let diff = e0.diff(&e1);
@@ -406,7 +1400,7 @@ mod tests {
 
     #[test]
     fn test_diff_2() {
-        let e0 = EventTree::Leaf(5);
+        let e0: EventTree = EventTree::Leaf(5);
         let e1 = EventTree::subtree(4, EventTree::Leaf(2), EventTree::Leaf(0));
 
         let diff = e1.diff(&e0);
@@ -415,25 +1409,344 @@ mod tests {
 
     #[test]
     fn test_diff_none() {
-        let e0 = EventTree::Leaf(5);
+        let e0: EventTree = EventTree::Leaf(5);
         let e1 = EventTree::subtree(4, EventTree::Leaf(1), EventTree::Leaf(1));
 
         let diff = e0.diff(&e1);
         assert_eq!(diff.to_string(), "0".to_string());
     }
 
+    #[test]
+    fn test_join_ref() {
+        use EventTree::*;
+
+        let e0: EventTree = EventTree::subtree(1, Leaf(0), EventTree::subtree(0, Leaf(2), Leaf(0)));
+        let e1 = EventTree::subtree(0, Leaf(4), Leaf(1));
+
+        let e2 = e0.join_ref(&e1);
+        assert_eq!(e2.to_string(), "(1, 3, (0, 2, 0))");
+        assert_eq!(e2, e1.join(e0));
+
+        // Unnormalized inputs still produce a normalized join
+        let e3: EventTree = EventTree::SubTree(0, Box::new(Leaf(2)), Box::new(Leaf(2)));
+        assert_eq!(e3.join_ref(&Leaf(1)), Leaf(2));
+    }
+
+    #[test]
+    fn test_join_assign() {
+        use EventTree::*;
+
+        let trees: [EventTree; 6] = [
+            Leaf(0),
+            Leaf(3),
+            EventTree::subtree(1, Leaf(0), EventTree::subtree(0, Leaf(2), Leaf(0))),
+            EventTree::subtree(0, Leaf(4), Leaf(1)),
+            EventTree::subtree(2, EventTree::subtree(0, Leaf(0), Leaf(3)), Leaf(0)),
+            EventTree::subtree(5, Leaf(0), Leaf(1)),
+        ];
+
+        for a in &trees {
+            for b in &trees {
+                let mut joined = a.clone();
+                joined.join_assign(b);
+                assert_eq!(joined, a.join_ref(b), "Joining {a} with {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_in_place() {
+        let ids = IdTree::one().fork_many(5);
+        let mut e = EventTree::new();
+
+        for (i, id) in ids.iter().cycle().take(23).enumerate() {
+            let before = e.clone();
+            e.event_in_place(id);
+            assert_eq!(e.relation(&before), Relation::HappenedAfter);
+            assert!(e.contains(id), "Event {i} was not recorded for {id}");
+        }
+
+        assert_eq!(e.to_string(), "(4, 1, 0)");
+    }
+
+    /// Checks that recording `n` events at once with `strategy` matches recording them one by one.
+    fn check_event_n<S: GrowthStrategy<u64>>(strategy: &S) {
+        let ids = IdTree::one().fork_many(3);
+        let mut ids = ids.into_iter().chain([IdTree::one()]).collect::<Vec<_>>();
+        ids.push(ids[0].clone().join(ids[2].clone()));
+
+        // Every stamp reachable in a few events from a handful of ids
+        let mut stamps: Vec<EventTree> = vec![EventTree::new()];
+        for i in 0..40 {
+            let e = stamps[i / 3]
+                .clone()
+                .event_with_strategy(&ids[i % ids.len()], strategy);
+            stamps.push(e);
+        }
+        // Leaves just below a wider counter, where growing once can change the cheapest leaf
+        stamps.push(EventTree::subtree(
+            0,
+            EventTree::Leaf(2),
+            EventTree::Leaf(11),
+        ));
+        stamps.push(EventTree::subtree(
+            0,
+            EventTree::Leaf(27),
+            EventTree::Leaf(3),
+        ));
+
+        for e in &stamps {
+            for id in &ids {
+                let mut sequential = e.clone();
+                for n in 1..=5 {
+                    sequential = sequential.event_with_strategy(id, strategy);
+                    let mut batched = e.clone();
+                    batched
+                        .event_n_in_place(id, n, OverflowPolicy::Error, strategy)
+                        .expect("Counters stay small");
+                    assert_eq!(
+                        batched.to_string(),
+                        sequential.to_string(),
+                        "Recording {n} events for {id} on {e}"
+                    );
+                }
+                let mut batched = e.clone();
+                batched
+                    .event_n_in_place(id, 0, OverflowPolicy::Error, strategy)
+                    .expect("Nothing to record");
+                assert_eq!(batched, *e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_n() {
+        check_event_n(&PaperGrowth);
+        check_event_n(&MinSizeGrowth);
+        check_event_n(&MinDepthGrowth);
+
+        let e: EventTree = EventTree::subtree(1, EventTree::Leaf(0), EventTree::Leaf(2));
+        let id = IdTree::subtree(IdTree::one(), IdTree::zero());
+        assert_eq!(
+            e.clone().event_n(&id, 3),
+            e.event(&id).event(&id).event(&id)
+        );
+    }
+
+    #[test]
+    fn test_event_n_overflow() {
+        use EventTree::*;
+
+        let (il, _) = IdTree::one().fork();
+        let e: EventTree<u8> = EventTree::subtree(200, Leaf(0), Leaf(50));
+
+        let mut e0 = e.clone();
+        assert_eq!(
+            e0.try_event_n_in_place_with(&il, 100, OverflowPolicy::Error),
+            Err(EventError::Overflow(OverflowError))
+        );
+        assert_eq!(e0, e);
+
+        e0.try_event_n_in_place_with(&il, 100, OverflowPolicy::Saturate)
+            .expect("Saturates");
+        assert_eq!(e0.to_string(), "(250, 5, 0)");
+        assert_eq!(
+            e0.try_event_n_in_place_with(&IdTree::zero(), 1, OverflowPolicy::Error),
+            Err(EventError::AnonymousId)
+        );
+    }
+
+    #[test]
+    fn test_try_event() {
+        let e: EventTree = EventTree::subtree(1, EventTree::Leaf(0), EventTree::Leaf(2));
+
+        assert_eq!(
+            e.clone().try_event(&IdTree::zero()),
+            Err(EventError::AnonymousId)
+        );
+        assert_eq!(
+            e.clone()
+                .try_event(&IdTree::subtree(IdTree::zero(), IdTree::zero())),
+            Err(EventError::AnonymousId)
+        );
+
+        let mut e0 = e.clone();
+        assert_eq!(
+            e0.try_event_in_place(&IdTree::zero()),
+            Err(EventError::AnonymousId)
+        );
+        assert_eq!(e0, e);
+
+        let id = IdTree::subtree(
+            IdTree::subtree(IdTree::zero(), IdTree::zero()),
+            IdTree::one(),
+        );
+        let e1 = e.try_event(&id).expect("Id owns the right half");
+        assert_eq!(e1.to_string(), "(1, 0, 3)");
+    }
+
+    #[test]
+    #[should_panic(expected = "anonymous id")]
+    fn test_event_anonymous_panics() {
+        EventTree::new().event(&IdTree::zero());
+    }
+
+    #[test]
+    fn test_validate() {
+        use EventTree::*;
+
+        let valid = EventTree::subtree(u64::MAX - 1, Leaf(0), Leaf(1));
+        let invalid = EventTree::subtree(u64::MAX, Leaf(0), Leaf(1));
+
+        assert_eq!(valid.validate(), Ok(()));
+        assert_eq!(invalid.validate(), Err(OverflowError));
+        assert_eq!(Leaf(u64::MAX).validate(), Ok(()));
+
+        assert_eq!(
+            invalid.clone().sanitize(OverflowPolicy::Error),
+            Err(OverflowError)
+        );
+        assert_eq!(
+            invalid.clone().sanitize(OverflowPolicy::Saturate),
+            Ok(Leaf(u64::MAX))
+        );
+        assert_eq!(
+            valid.clone().sanitize(OverflowPolicy::Saturate),
+            Ok(valid.clone())
+        );
+
+        let mut joined = valid.clone();
+        assert_eq!(
+            joined.try_join_assign(&invalid, OverflowPolicy::Error),
+            Err(OverflowError)
+        );
+        assert_eq!(joined, valid);
+        assert_eq!(
+            invalid
+                .clone()
+                .try_join(valid.clone(), OverflowPolicy::Error),
+            Err(OverflowError)
+        );
+        assert_eq!(
+            valid.clone().try_join(Leaf(1), OverflowPolicy::Error),
+            Ok(valid.clone())
+        );
+        assert_eq!(
+            valid
+                .clone()
+                .try_join(invalid.clone(), OverflowPolicy::Saturate),
+            Ok(Leaf(u64::MAX))
+        );
+        assert_eq!(
+            Leaf(3).try_join(invalid.clone(), OverflowPolicy::Saturate),
+            Ok(Leaf(u64::MAX))
+        );
+
+        // None of the operations panic on an invalid tree
+        let _ = invalid.join_ref(&valid);
+        let _ = invalid.diff(&valid);
+        let _ = valid.diff(&invalid);
+        let _ = invalid.mask(&valid);
+        let _ = invalid.relation(&valid);
+    }
+
+    #[test]
+    fn test_event_overflow() {
+        use EventTree::*;
+
+        let mut e = Leaf(u64::MAX);
+        assert_eq!(
+            e.try_event_in_place(&IdTree::one()),
+            Err(EventError::Overflow(OverflowError))
+        );
+        assert_eq!(e, Leaf(u64::MAX));
+
+        e.try_event_in_place_with(&IdTree::one(), OverflowPolicy::Saturate)
+            .expect("Saturates");
+        assert_eq!(e, Leaf(u64::MAX));
+
+        let e = EventTree::subtree(u64::MAX - 1, Leaf(1), Leaf(0));
+        let (il, ir) = IdTree::one().fork();
+
+        let mut e0 = e.clone();
+        assert_eq!(
+            e0.try_event_in_place(&il),
+            Err(EventError::Overflow(OverflowError))
+        );
+        assert_eq!(e0, e);
+
+        // Filling never increases the maximum so cannot overflow
+        let mut e1 = e.clone();
+        e1.try_event_in_place(&ir).expect("Fills the right side");
+        assert_eq!(e1, Leaf(u64::MAX));
+    }
+
+    #[test]
+    fn test_counter_widths() {
+        let (il, ir) = IdTree::one().fork();
+
+        let mut e: EventTree<u32> = EventTree::Leaf(u32::MAX - 1);
+        e.event_in_place(&il);
+        assert_eq!(e.to_string(), format!("({}, 1, 0)", u32::MAX - 1));
+        assert_eq!(
+            e.try_event_in_place(&il),
+            Err(EventError::Overflow(OverflowError))
+        );
+        e.event_in_place(&ir);
+        assert_eq!(e, EventTree::Leaf(u32::MAX));
+
+        let e: EventTree<u128> = EventTree::Leaf(u64::MAX as u128);
+        assert_eq!(
+            e.event(&IdTree::one()),
+            EventTree::Leaf(u64::MAX as u128 + 1)
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        use EventTree::*;
+
+        let e: EventTree = EventTree::subtree(0, EventTree::subtree(0, Leaf(0), Leaf(1)), Leaf(0));
+        let (il, ir) = IdTree::one().fork();
+        let (ill, ilr) = il.clone().fork();
+
+        assert!(e.contains(&IdTree::one()));
+        assert!(!Leaf(0u64).contains(&il));
+        assert!(Leaf(1u64).contains(&ir));
+        assert!(!EventTree::subtree(0u64, Leaf(0), Leaf(0)).contains(&IdTree::one()));
+        assert!(!e.contains(&IdTree::zero()));
+
+        // Structured ids are not followed into subtrees, even where the values lie elsewhere
+        assert!(e.contains(&ilr));
+        assert!(e.contains(&ill));
+        assert!(e.contains(&ir));
+    }
+
     #[test]
     fn test_norm() {
-        let e = EventTree::subtree(0, EventTree::Leaf(0), EventTree::Leaf(0));
-        let e = e.norm();
+        use EventTree::*;
+
+        let e: EventTree = SubTree(0, Box::new(Leaf(0)), Box::new(Leaf(0)));
+        assert!(!e.is_normalized());
+        let e = e.normalize();
+        assert!(e.is_normalized());
         assert_eq!(e.to_string(), "0".to_string());
+
+        let e: EventTree = SubTree(
+            1,
+            Box::new(SubTree(2, Box::new(Leaf(1)), Box::new(Leaf(3)))),
+            Box::new(Leaf(4)),
+        );
+        assert_eq!(e.clone().normalize().to_string(), "(4, (0, 0, 2), 1)");
+        assert_eq!(EventTree::subtree(1, Leaf(3), Leaf(3)), Leaf(4u64));
+        assert!(EventTree::subtree(1u64, Leaf(3), Leaf(5)).is_normalized());
     }
 
     #[test]
     fn test_mask_0() {
         use EventTree::*;
 
-        let e0 = EventTree::subtree(5, Leaf(1), Leaf(0));
+        let e0: EventTree = EventTree::subtree(5, Leaf(1), Leaf(0));
         let e1 = EventTree::subtree(0, Leaf(0), Leaf(2));
 
         let e = e0.mask(&e1);
@@ -447,7 +1760,7 @@ mod tests {
     fn test_mask_1() {
         use EventTree::*;
 
-        let e0 = EventTree::subtree(0, Leaf(1), Leaf(0));
+        let e0: EventTree = EventTree::subtree(0, Leaf(1), Leaf(0));
         let e1 = EventTree::subtree(0, Leaf(0), Leaf(2));
 
         let e = e0.mask(&e1);
@@ -461,7 +1774,7 @@ mod tests {
     fn test_mask_2() {
         use EventTree::*;
 
-        let e0 = EventTree::subtree(2, Leaf(1), Leaf(0));
+        let e0: EventTree = EventTree::subtree(2, Leaf(1), Leaf(0));
         let e1 = EventTree::subtree(1, Leaf(0), Leaf(2));
 
         let e = e0.mask(&e1);
@@ -470,4 +1783,266 @@ mod tests {
         let e = e1.mask(&e0);
         assert_eq!(e.to_string(), "(1, 0, 2)");
     }
+
+    #[test]
+    fn test_hash_normalized() {
+        use EventTree::*;
+        use std::collections::HashMap;
+
+        let mut map: HashMap<EventTree, _> = HashMap::new();
+        map.insert(EventTree::subtree(1, Leaf(0), Leaf(2)), "stamp");
+        let unnormalized = SubTree(
+            0,
+            Box::new(SubTree(1, Box::new(Leaf(0)), Box::new(Leaf(0)))),
+            Box::new(Leaf(3)),
+        );
+        assert_eq!(unnormalized, EventTree::subtree(1, Leaf(0), Leaf(2)));
+        assert_eq!(map.get(&unnormalized), Some(&"stamp"));
+        assert_eq!(map.get(&Leaf(1)), None);
+    }
+
+    #[test]
+    fn test_hash_overflowing() {
+        use EventTree::*;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        fn hash_of(e: &EventTree) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            e.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // Both sum past the counter on the left, so they equal the saturated leaf
+        let normalized = SubTree(u64::MAX, Box::new(Leaf(1)), Box::new(Leaf(0)));
+        let unnormalized = SubTree(u64::MAX, Box::new(Leaf(1)), Box::new(Leaf(1)));
+        for e in [normalized, unnormalized] {
+            assert_eq!(e, Leaf(u64::MAX));
+            assert_eq!(hash_of(&e), hash_of(&Leaf(u64::MAX)));
+            assert_eq!(
+                TotalOrd(e.clone()).cmp(&TotalOrd(Leaf(u64::MAX))),
+                Ordering::Equal
+            );
+        }
+    }
+
+    fn lattice_stamps() -> Vec<EventTree> {
+        use EventTree::*;
+        let ids = IdTree::one().fork_many(3);
+        let mut stamps = vec![EventTree::new()];
+        for (i, id) in ids.iter().cycle().take(9).enumerate() {
+            let e = stamps[i / 2].clone().event(id);
+            stamps.push(e);
+        }
+        stamps.push(SubTree(
+            0,
+            Box::new(SubTree(1, Box::new(Leaf(0)), Box::new(Leaf(0)))),
+            Box::new(Leaf(3)),
+        ));
+        stamps
+    }
+
+    #[test]
+    fn test_restrict_exclude() {
+        use EventTree::*;
+
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let mut e: EventTree = EventTree::new();
+        for id in [&a, &b, &b, &c, &c, &c] {
+            e.event_in_place(id);
+        }
+
+        assert_eq!(e.restrict(&a).to_string(), "(0, 1, 0)");
+        assert_eq!(e.restrict(&c).value_at(&c), e.value_at(&c));
+        assert_eq!(e.restrict(&c).value_at(&a), Some(0..=0));
+        assert_eq!(e.restrict(&IdTree::one()), e);
+        assert_eq!(e.restrict(&IdTree::zero()), Leaf(0));
+        assert_eq!(e.exclude(&IdTree::zero()), e);
+        assert_eq!(e.exclude(&a), e.restrict(&b.clone().join(c.clone())));
+
+        // The two halves of a stamp join back into it and are never ahead of it
+        let bc = b.join(c);
+        assert_eq!(e.restrict(&bc).join(e.exclude(&bc)), e);
+        assert!(e.restrict(&bc) <= e && e.exclude(&bc) <= e);
+
+        let unnormalized: EventTree = SubTree(2, Box::new(Leaf(1)), Box::new(Leaf(1)));
+        assert_eq!(unnormalized.restrict(&a).to_string(), "(0, 3, 0)");
+        assert!(unnormalized.exclude(&a).is_normalized());
+    }
+
+    #[test]
+    fn test_meet() {
+        use EventTree::*;
+
+        let a: EventTree = EventTree::subtree(1, Leaf(0), Leaf(2));
+        let b = EventTree::subtree(0, Leaf(4), Leaf(1));
+        assert_eq!(a.meet(&b), Leaf(1));
+        let c = EventTree::subtree(0, EventTree::subtree(0, Leaf(3), Leaf(0)), Leaf(2));
+        assert_eq!(a.meet(&c).to_string(), "(0, (0, 1, 0), 2)");
+        assert_eq!(a.meet(&Leaf(0)), Leaf(0));
+        assert_eq!(a.meet(&Leaf(5)), a);
+    }
+
+    #[test]
+    fn test_lattice_laws() {
+        let stamps = lattice_stamps();
+        for a in &stamps {
+            assert_eq!(a.meet(a), *a);
+            assert_eq!(a.join_ref(a), *a);
+            for b in &stamps {
+                let meet = a.meet(b);
+                let join = a.join_ref(b);
+                assert_eq!(meet, b.meet(a));
+                assert_eq!(join, b.join_ref(a));
+                assert_eq!(a.join_ref(&meet), *a, "Absorbing {a} and {b}");
+                assert_eq!(a.meet(&join), *a, "Absorbing {a} and {b}");
+                assert!(meet <= *a && meet <= *b);
+                assert_eq!(a <= b, meet == *a);
+                for c in &stamps {
+                    assert_eq!(meet.meet(c), a.meet(&b.meet(c)));
+                    assert_eq!(join.join_ref(c), a.join_ref(&b.join_ref(c)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_all_meet_all() {
+        let stamps = lattice_stamps();
+        for n in 1..=stamps.len() {
+            for window in stamps.windows(n) {
+                let join = window[1..]
+                    .iter()
+                    .fold(window[0].clone(), |acc, e| acc.join_ref(e));
+                let meet = window[1..]
+                    .iter()
+                    .fold(window[0].clone(), |acc, e| acc.meet(e));
+                assert_eq!(EventTree::join_all(window), join);
+                assert_eq!(EventTree::meet_all(window), Some(meet));
+            }
+        }
+
+        assert_eq!(EventTree::<u64>::join_all([]), EventTree::new());
+        assert_eq!(EventTree::<u64>::meet_all([]), None);
+    }
+
+    #[test]
+    fn test_total_cmp() {
+        use EventTree::*;
+        use std::collections::BTreeMap;
+
+        let ids = IdTree::one().fork_many(3);
+        let mut e: EventTree = EventTree::new();
+        let mut stamps = vec![e.clone()];
+        for id in ids.iter().cycle().take(7) {
+            e.event_in_place(id);
+            stamps.push(e.clone());
+        }
+        stamps.push(EventTree::subtree(0, Leaf(5), Leaf(0)));
+        stamps.push(EventTree::subtree(0, Leaf(0), Leaf(5)));
+
+        for a in &stamps {
+            for b in &stamps {
+                let expected = match a.relation(b) {
+                    Relation::HappenedBefore => Some(Ordering::Less),
+                    Relation::HappenedAfter => Some(Ordering::Greater),
+                    Relation::Equal => Some(Ordering::Equal),
+                    Relation::Concurrent => None,
+                };
+                let ordering = a.total_cmp(b);
+                assert_eq!(ordering, b.total_cmp(a).reverse());
+                assert!(
+                    expected.is_none_or(|o| o == ordering),
+                    "Comparing {a} with {b}"
+                );
+                assert_eq!(ordering == Ordering::Equal, a == b);
+            }
+        }
+
+        // Unnormalized trees sort along with their normalized form
+        let mut index: BTreeMap<TotalOrd, _> = BTreeMap::new();
+        index.insert(TotalOrd(EventTree::subtree(1, Leaf(0), Leaf(2))), "stamp");
+        let unnormalized = SubTree(
+            0,
+            Box::new(SubTree(1, Box::new(Leaf(0)), Box::new(Leaf(0)))),
+            Box::new(Leaf(3)),
+        );
+        assert_eq!(index.get(&TotalOrd(unnormalized)), Some(&"stamp"));
+        assert!(TotalOrd::<u64>(Leaf(1)) < TotalOrd(EventTree::subtree(1, Leaf(0), Leaf(2))));
+        assert!(TotalOrd::<u64>(EventTree::subtree(0, Leaf(0), Leaf(5))) < TotalOrd(Leaf(1)));
+    }
+
+    #[test]
+    fn test_deep_trees() {
+        use crate::DecodeError;
+        use EventTree::*;
+        use std::collections::HashSet;
+
+        const DEPTH: usize = 50_000;
+
+        // The rightmost of `DEPTH` nested halvings of the interval
+        let mut id = IdTree::One;
+        let mut e: EventTree = Leaf(0);
+        let mut unnormalized: EventTree = Leaf(0);
+        for _ in 0..DEPTH {
+            id = IdTree::SubTree(Box::new(IdTree::Zero), Box::new(id));
+            e = SubTree(0, Box::new(Leaf(1)), Box::new(e));
+            unnormalized = SubTree(0, Box::new(Leaf(0)), Box::new(unnormalized));
+        }
+
+        let copy = e.clone();
+        assert_eq!(copy, e);
+        assert!(e.is_normalized());
+        assert_eq!(e.to_string().len(), copy.to_string().len());
+        let set = HashSet::from([copy.clone()]);
+        assert!(set.contains(&e));
+        assert_eq!(unnormalized.normalize(), Leaf(0));
+
+        // Filling the only gap collapses the whole tree
+        let filled = e.clone().event(&id);
+        assert_eq!(filled, Leaf(1));
+        assert_eq!(e.relation(&filled), Relation::HappenedBefore);
+        assert_eq!(e.join_ref(&filled), filled);
+        let diff = filled.diff(&e);
+        assert_eq!(diff.to_string().len(), e.to_string().len());
+        let masked = e.mask(&e);
+        assert_eq!(masked, e);
+        assert!(e.contains(&IdTree::One));
+        assert!(e.validate().is_ok());
+
+        // Growing has to expand a leaf at every level
+        let grown: EventTree = EventTree::new().event(&id);
+        assert_eq!(grown.relation(&e), Relation::Concurrent);
+        let joined = grown.join(e.clone());
+        assert_eq!(joined, Leaf(1));
+
+        assert!(matches!(
+            EventTree::<u64>::decode(&e.encode()),
+            Err(DecodeError::TooDeep(_))
+        ));
+
+        // Dropping as usual would recurse once per level
+        for e in set.into_iter().chain([e, copy, diff, masked]) {
+            e.drop_iteratively();
+        }
+        id.drop_iteratively();
+
+        // Growing chooses between two owned children at every level, all the way to the bottom
+        let mut id = IdTree::One;
+        let mut e: EventTree = Leaf(5);
+        let mut expected: EventTree = Leaf(6);
+        for _ in 0..DEPTH {
+            let right = IdTree::SubTree(Box::new(IdTree::One), Box::new(IdTree::Zero));
+            id = IdTree::SubTree(Box::new(id), Box::new(right));
+            e = SubTree(0, Box::new(e), Box::new(Leaf(0)));
+            expected = SubTree(0, Box::new(expected), Box::new(Leaf(0)));
+        }
+        assert!(e.is_normalized());
+        let grown = e.event(&id);
+        assert_eq!(grown, expected);
+        grown.drop_iteratively();
+        expected.drop_iteratively();
+        id.drop_iteratively();
+    }
 }
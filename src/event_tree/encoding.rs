@@ -0,0 +1,231 @@
+use super::*;
+use crate::encoding::{BitReader, BitWriter, Children, DecodeError, DecodeOptions};
+
+impl<C: Counter> EventTree<C> {
+    /// Encodes the event tree using the compact bit encoding from the original paper.
+    ///
+    /// Trees nested deeper than [`MAX_DECODE_DEPTH`](crate::MAX_DECODE_DEPTH) have to be decoded
+    /// with a larger [`DecodeOptions::max_depth`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        self.encode_into(&mut writer);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_with(bytes, &DecodeOptions::default())
+    }
+
+    pub fn decode_with(bytes: &[u8], options: &DecodeOptions) -> Result<Self, DecodeError> {
+        let mut reader = BitReader::with_options(bytes, options);
+        let event = Self::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(event)
+    }
+
+    pub(crate) fn encode_into(&self, writer: &mut BitWriter) {
+        use EventTree::*;
+        let is_zero = |e: &Self| matches!(e, Leaf(m) if *m == C::ZERO);
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            let (n, l, r) = match e {
+                Leaf(n) => {
+                    writer.write_counter(*n);
+                    continue;
+                }
+                SubTree(n, l, r) => (*n, l.as_ref(), r.as_ref()),
+            };
+
+            writer.write_bit(false);
+            match (n == C::ZERO, is_zero(l), is_zero(r)) {
+                (true, true, _) => {
+                    writer.write_bits(0, 2);
+                    stack.push(r);
+                }
+                (true, _, true) => {
+                    writer.write_bits(1, 2);
+                    stack.push(l);
+                }
+                (true, _, _) => {
+                    writer.write_bits(2, 2);
+                    stack.push(r);
+                    stack.push(l);
+                }
+                (false, true, _) => {
+                    writer.write_bits(0b1100, 4);
+                    writer.write_counter(n);
+                    stack.push(r);
+                }
+                (false, _, true) => {
+                    writer.write_bits(0b1101, 4);
+                    writer.write_counter(n);
+                    stack.push(l);
+                }
+                (false, _, _) => {
+                    writer.write_bits(0b111, 3);
+                    writer.write_counter(n);
+                    stack.push(r);
+                    stack.push(l);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn decode_from(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        use EventTree::*;
+
+        // Nodes whose children are still being decoded, along with the left child once it is
+        let mut frames: Vec<(C, Children, Option<Self>)> = vec![];
+        loop {
+            let mut event = if reader.read_bit()? {
+                Leaf(reader.read_number()?)
+            } else {
+                reader.check_depth(frames.len())?;
+                let (n, children) = match reader.read_bits(2)? {
+                    0 => (C::ZERO, Children::Right),
+                    1 => (C::ZERO, Children::Left),
+                    2 => (C::ZERO, Children::Both),
+                    _ => match (reader.read_bit()?, reader.read_bit()?) {
+                        (true, next) => {
+                            // ⟨1, 1⟩ is followed directly by enc(n), which starts with its own ⟨1, 1⟩.
+                            if !next {
+                                return Err(DecodeError::InvalidValue(reader.position() - 1));
+                            }
+                            (reader.read_number()?, Children::Both)
+                        }
+                        (false, false) => (reader.read_counter()?, Children::Right),
+                        (false, true) => (reader.read_counter()?, Children::Left),
+                    },
+                };
+                frames.push((n, children, None));
+                continue;
+            };
+
+            // Builds every node whose last child has just been decoded
+            loop {
+                event = match frames.pop() {
+                    None => return Ok(event),
+                    Some((n, Children::Right, _)) => Self::node(n, Leaf(C::ZERO), event),
+                    Some((n, Children::Left, _)) => Self::node(n, event, Leaf(C::ZERO)),
+                    Some((n, Children::Both, Some(l))) => Self::node(n, l, event),
+                    Some((n, Children::Both, None)) => {
+                        frames.push((n, Children::Both, Some(event)));
+                        break;
+                    }
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_golden() {
+        use EventTree::*;
+
+        let vectors: [(EventTree, _); 7] = [
+            (Leaf(0), vec![0x80]),
+            (Leaf(1), vec![0x90]),
+            (Leaf(3), vec![0xb0]),
+            (Leaf(4), vec![0xc0]),
+            (Leaf(5), vec![0xc4]),
+            (EventTree::subtree(0, Leaf(1), Leaf(0)), vec![0x32]),
+            (
+                EventTree::subtree(1, Leaf(0), EventTree::subtree(0, Leaf(1), Leaf(0))),
+                vec![0x64, 0x99],
+            ),
+        ];
+
+        for (event, bytes) in vectors {
+            assert_eq!(event.encode(), bytes, "Encoding {event}");
+            assert_eq!(EventTree::decode(&bytes), Ok(event));
+        }
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        use EventTree::*;
+
+        let events = [
+            EventTree::subtree(0, Leaf(3), Leaf(7)),
+            EventTree::subtree(9, Leaf(3), Leaf(0)),
+            EventTree::subtree(9, Leaf(3), Leaf(7)),
+            EventTree::subtree(
+                2,
+                EventTree::subtree(0, Leaf(0), EventTree::subtree(1, Leaf(0), Leaf(3))),
+                EventTree::subtree(4, Leaf(0), EventTree::subtree(0, Leaf(0), Leaf(1))),
+            ),
+            EventTree::subtree(u64::MAX - 1, Leaf(0), Leaf(1)),
+            Leaf(u64::MAX),
+        ];
+
+        for event in events {
+            assert_eq!(EventTree::decode(&event.encode()), Ok(event));
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let decode = EventTree::<u64>::decode;
+
+        assert_eq!(decode(&[]), Err(DecodeError::UnexpectedEnd(0)));
+        assert_eq!(decode(&[0x80, 0x00]), Err(DecodeError::TrailingData(4)));
+        assert_eq!(decode(&[0x00; 300]), Err(DecodeError::TooDeep(1537)));
+    }
+
+    #[test]
+    fn test_decode_deep() {
+        use EventTree::*;
+
+        // Far deeper than decoding could recurse through
+        const DEPTH: usize = 100_000;
+        let mut e: EventTree = Leaf(1);
+        for i in 0..DEPTH {
+            e = SubTree(1, Box::new(Leaf(0)), Box::new(e));
+            if i % 2 == 0 {
+                e = SubTree(0, Box::new(e), Box::new(Leaf(2)));
+            }
+        }
+        let depth = DEPTH + DEPTH / 2;
+
+        let bytes = e.encode();
+        assert!(matches!(
+            EventTree::<u64>::decode(&bytes),
+            Err(DecodeError::TooDeep(_))
+        ));
+        let options = DecodeOptions::new().max_depth(depth - 1);
+        assert!(matches!(
+            EventTree::<u64>::decode_with(&bytes, &options),
+            Err(DecodeError::TooDeep(_))
+        ));
+
+        let options = DecodeOptions::new().max_depth(depth);
+        let decoded = EventTree::decode_with(&bytes, &options).expect("Deep enough");
+        assert_eq!(decoded, e);
+        decoded.drop_iteratively();
+        e.drop_iteratively();
+    }
+
+    #[test]
+    fn test_encode_widths() {
+        use EventTree::*;
+
+        // The encoding does not depend on the counter width, only the range accepted when decoding
+        let narrow: EventTree<u32> = EventTree::subtree(9, Leaf(3), Leaf(0));
+        let wide: EventTree = EventTree::subtree(9, Leaf(3), Leaf(0));
+        assert_eq!(narrow.encode(), wide.encode());
+        assert_eq!(EventTree::decode(&wide.encode()), Ok(narrow));
+
+        let large: EventTree = EventTree::subtree(u32::MAX as u64 + 1, Leaf(0), Leaf(1));
+        assert_eq!(
+            EventTree::<u32>::decode(&large.encode()),
+            Err(DecodeError::Overflow(6))
+        );
+
+        let huge: EventTree<u128> = EventTree::subtree(u128::MAX - 1, Leaf(0), Leaf(1));
+        assert_eq!(EventTree::decode(&huge.encode()), Ok(huge));
+    }
+}
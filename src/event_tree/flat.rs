@@ -0,0 +1,716 @@
+use super::*;
+use crate::FlatIdTree;
+use crate::flat::subtree_ends;
+use crate::id_tree::flat::Node as IdNode;
+
+/// An [`EventTree`] laid out in preorder within a single `Vec`, avoiding an allocation per node
+/// and making a clone a single copy.
+///
+/// Always kept normalized, so timestamps are equal exactly when their nodes are.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlatEventTree<C: Counter = u64> {
+    nodes: Vec<Node<C>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Node<C> {
+    Leaf(C),
+    /// Followed by the left and then the right subtree.
+    SubTree(C),
+}
+
+impl<C: Counter> Node<C> {
+    fn value(self) -> C {
+        match self {
+            Node::Leaf(val) | Node::SubTree(val) => val,
+        }
+    }
+
+    fn value_mut(&mut self) -> &mut C {
+        match self {
+            Node::Leaf(val) | Node::SubTree(val) => val,
+        }
+    }
+}
+
+/// A position within a tree being grown; `None` is a leaf expanded on the way down.
+type Pos = Option<usize>;
+
+/// A tree along with the extent of each of its subtrees.
+struct Cursor<'a, C: Counter> {
+    nodes: &'a [Node<C>],
+    ends: Vec<usize>,
+}
+
+impl<'a, C: Counter> Cursor<'a, C> {
+    fn new(tree: &'a FlatEventTree<C>) -> Self {
+        let nodes = tree.nodes.as_slice();
+        let ends = subtree_ends(nodes.len(), |i| matches!(nodes[i], Node::Leaf(_)));
+        Self { nodes, ends }
+    }
+
+    /// The children of the node at `i` along with their bases. A leaf stands in for both of its
+    /// own children.
+    fn children(&self, (i, base): (usize, C)) -> [(usize, C); 2] {
+        match self.nodes[i] {
+            Node::Leaf(_) => [(i, base); 2],
+            Node::SubTree(val) => {
+                let base = base.saturating_add(val);
+                [(i + 1, base), (self.ends[i + 1], base)]
+            }
+        }
+    }
+
+    fn subtree(&self, i: usize) -> &'a [Node<C>] {
+        &self.nodes[i..self.ends[i]]
+    }
+
+    /// The largest value in the subtree at `i`, including the subtree's own value.
+    fn max(&self, i: usize) -> C {
+        let mut max = C::ZERO;
+        let mut stack = vec![(i, C::ZERO)];
+        while let Some(pos) = stack.pop() {
+            match self.nodes[pos.0] {
+                Node::Leaf(val) => max = max.max(pos.1.saturating_add(val)),
+                Node::SubTree(_) => stack.extend(self.children(pos)),
+            }
+        }
+        max
+    }
+
+    /// Whether the node at `pos` is a leaf; expanded leaves always are.
+    fn is_leaf(&self, pos: Pos) -> bool {
+        pos.is_none_or(|i| matches!(self.nodes[i], Node::Leaf(_)))
+    }
+
+    /// The children of `pos`, expanding a leaf into two zero leaves.
+    fn expand(&self, pos: Pos) -> (C, Pos, Pos) {
+        match pos.map(|i| (i, self.nodes[i])) {
+            None => (C::ZERO, None, None),
+            Some((_, Node::Leaf(val))) => (val, None, None),
+            Some((i, Node::SubTree(val))) => (val, Some(i + 1), Some(self.ends[i + 1])),
+        }
+    }
+}
+
+impl FlatEventTree {
+    /// Creates an empty `u64` timestamp; other counter widths start from
+    /// `FlatEventTree::default()`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Counter> FlatEventTree<C> {
+    pub fn join(&self, other: &Self) -> Self {
+        use Node::*;
+        let (a, b) = (Cursor::new(self), Cursor::new(other));
+        Self::zip_with(&a, &b, |nodes, (i, a_base), (j, b_base)| {
+            match (a.nodes[i], b.nodes[j]) {
+                (Leaf(x), Leaf(y)) => {
+                    nodes.push(Leaf(a_base.saturating_add(x).max(b_base.saturating_add(y))))
+                }
+                (Leaf(x), SubTree(y)) if a_base.saturating_add(x) <= b_base.saturating_add(y) => {
+                    Self::push_lifted(nodes, b.subtree(j), b_base)
+                }
+                (SubTree(x), Leaf(y)) if b_base.saturating_add(y) <= a_base.saturating_add(x) => {
+                    Self::push_lifted(nodes, a.subtree(i), a_base)
+                }
+                _ => return false,
+            }
+            true
+        })
+    }
+
+    /// Records an event for `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is anonymous; see [`FlatEventTree::try_event`].
+    pub fn event(self, id: &FlatIdTree) -> Self {
+        match self.try_event(id) {
+            Ok(event) => event,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Records an event for `id`, failing if the id owns no part of the interval.
+    pub fn try_event(self, id: &FlatIdTree) -> Result<Self, EventError> {
+        if id.is_anonymous() {
+            return Err(EventError::AnonymousId);
+        }
+
+        let filled = self.fill(id);
+        if filled != self {
+            return Ok(filled);
+        }
+
+        #[allow(non_snake_case)]
+        let N = self.depth();
+        Ok(self.grow(id, N + 1)?)
+    }
+
+    /// Saturating substraction of the other FlatEventTree
+    pub fn diff(&self, other: &Self) -> Self {
+        use Node::*;
+        let (a, b) = (Cursor::new(self), Cursor::new(other));
+        Self::zip_with(&a, &b, |nodes, (i, a_base), (j, b_base)| {
+            match (a.nodes[i], b.nodes[j]) {
+                (Leaf(x), Leaf(y)) => nodes.push(Leaf(
+                    a_base
+                        .saturating_add(x)
+                        .saturating_sub(b_base.saturating_add(y)),
+                )),
+                (Leaf(x), SubTree(y)) if a_base.saturating_add(x) <= b_base.saturating_add(y) => {
+                    nodes.push(Leaf(C::ZERO))
+                }
+                (SubTree(x), Leaf(y)) if b_base.saturating_add(y) <= a_base.saturating_add(x) => {
+                    let start = nodes.len();
+                    Self::push_lifted(nodes, a.subtree(i), a_base);
+                    *nodes[start].value_mut() -= b_base.saturating_add(y);
+                }
+                _ => return false,
+            }
+            true
+        })
+    }
+
+    /// Returns a FlatEventTree with values only where `other` is non-zero.
+    pub fn mask(&self, other: &Self) -> Self {
+        use Node::*;
+        let (a, mask) = (Cursor::new(self), Cursor::new(other));
+        // Splitting only continues below zero-valued nodes of the mask, so its base stays zero.
+        Self::zip_with(&a, &mask, |nodes, (i, a_base), (j, _)| {
+            match mask.nodes[j] {
+                Leaf(m) if m == C::ZERO => nodes.push(Leaf(C::ZERO)),
+                SubTree(m) if m == C::ZERO => return false,
+                _ => Self::push_lifted(nodes, a.subtree(i), a_base),
+            }
+            true
+        })
+    }
+
+    /// Computes the causal relation to `other` in a single traversal of both trees.
+    pub fn relation(&self, other: &Self) -> Relation {
+        let (a, b) = (Cursor::new(self), Cursor::new(other));
+
+        // Cleared whenever a point is found where `self` is greater or lesser than `other`
+        let mut le = true;
+        let mut ge = true;
+
+        let mut stack = vec![((0, C::ZERO), (0, C::ZERO))];
+        while let Some((i, j)) = stack.pop() {
+            if let (Node::Leaf(x), Node::Leaf(y)) = (a.nodes[i.0], b.nodes[j.0]) {
+                match i.1.saturating_add(x).cmp(&j.1.saturating_add(y)) {
+                    Ordering::Less => ge = false,
+                    Ordering::Greater => le = false,
+                    Ordering::Equal => {}
+                }
+                if !le && !ge {
+                    break;
+                }
+            } else {
+                let [il, ir] = a.children(i);
+                let [jl, jr] = b.children(j);
+                stack.push((ir, jr));
+                stack.push((il, jl));
+            }
+        }
+
+        match (le, ge) {
+            (true, true) => Relation::Equal,
+            (true, false) => Relation::HappenedBefore,
+            (false, true) => Relation::HappenedAfter,
+            (false, false) => Relation::Concurrent,
+        }
+    }
+
+    /// Walks `a` and `b` together, carrying the accumulated base of each side. Wherever `visit`
+    /// declines to write a result, the children are visited and combined under a zero node.
+    fn zip_with<F>(a: &Cursor<C>, b: &Cursor<C>, mut visit: F) -> Self
+    where
+        F: FnMut(&mut Vec<Node<C>>, (usize, C), (usize, C)) -> bool,
+    {
+        enum Frame<C> {
+            Visit((usize, C), (usize, C)),
+            Close(usize),
+        }
+
+        let mut nodes = vec![];
+        let mut starts = vec![];
+        let mut frames = vec![Frame::Visit((0, C::ZERO), (0, C::ZERO))];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(i, j) => {
+                    let start = nodes.len();
+                    if visit(&mut nodes, i, j) {
+                        starts.push(start);
+                        continue;
+                    }
+
+                    nodes.push(Node::SubTree(C::ZERO));
+                    let [il, ir] = a.children(i);
+                    let [jl, jr] = b.children(j);
+                    frames.push(Frame::Close(start));
+                    frames.push(Frame::Visit(ir, jr));
+                    frames.push(Frame::Visit(il, jl));
+                }
+                Frame::Close(p) => Self::close_last(&mut nodes, &mut starts, p),
+            }
+        }
+        Self { nodes }
+    }
+
+    /// Fills the regions owned by `id`, as in [`EventTree::event`].
+    fn fill(&self, id: &FlatIdTree) -> Self {
+        use Node::*;
+
+        enum Frame {
+            Visit(usize, usize),
+            /// Close a node whose left child is a placeholder to fill from the given subtree.
+            FillLeft(usize, usize),
+            /// Close a node whose right child is still to be filled from the given subtree.
+            FillRight(usize, usize),
+            Close(usize),
+        }
+
+        let e = Cursor::new(self);
+        let ids = id.nodes();
+        let id_ends = id.ends();
+
+        let mut nodes: Vec<Node<C>> = vec![];
+        let mut starts = vec![];
+        let mut frames = vec![Frame::Visit(0, 0)];
+        while let Some(frame) = frames.pop() {
+            let (i, j) = match frame {
+                Frame::Visit(i, j) => (i, j),
+                Frame::FillLeft(p, el) => {
+                    let r: usize = *starts.last().expect("Right child is filled");
+                    nodes[p + 1] = Leaf(e.max(el).max(nodes[r].value()));
+                    Self::close_last(&mut nodes, &mut starts, p);
+                    continue;
+                }
+                Frame::FillRight(p, er) => {
+                    starts.push(nodes.len());
+                    nodes.push(Leaf(e.max(er).max(nodes[p + 1].value())));
+                    Self::close_last(&mut nodes, &mut starts, p);
+                    continue;
+                }
+                Frame::Close(p) => {
+                    Self::close_last(&mut nodes, &mut starts, p);
+                    continue;
+                }
+            };
+
+            match (e.nodes[i], ids[j]) {
+                (_, IdNode::Zero) | (Leaf(_), _) => {
+                    starts.push(nodes.len());
+                    nodes.extend_from_slice(e.subtree(i));
+                }
+                (_, IdNode::One) => {
+                    starts.push(nodes.len());
+                    nodes.push(Leaf(e.max(i)));
+                }
+                (SubTree(val), IdNode::SubTree) => {
+                    let p = nodes.len();
+                    nodes.push(SubTree(val));
+                    let (el, er) = (i + 1, e.ends[i + 1]);
+                    let (il, ir) = (j + 1, id_ends[j + 1]);
+                    match (ids[il], ids[ir]) {
+                        (IdNode::One, _) => {
+                            starts.push(nodes.len());
+                            nodes.push(Leaf(C::ZERO));
+                            frames.push(Frame::FillLeft(p, el));
+                            frames.push(Frame::Visit(er, ir));
+                        }
+                        (_, IdNode::One) => {
+                            frames.push(Frame::FillRight(p, er));
+                            frames.push(Frame::Visit(el, il));
+                        }
+                        _ => {
+                            frames.push(Frame::Close(p));
+                            frames.push(Frame::Visit(er, ir));
+                            frames.push(Frame::Visit(el, il));
+                        }
+                    }
+                }
+            }
+        }
+        Self { nodes }
+    }
+
+    /// Grows the tree under `id` along the path to the leaf which is cheapest to increment.
+    #[allow(non_snake_case)]
+    fn grow(&self, id: &FlatIdTree, N: u64) -> Result<Self, OverflowError> {
+        enum Frame {
+            /// A subtree and its id, along with the entry for both in the costs.
+            Visit(Pos, usize, usize),
+            Copy(Pos),
+            Close(usize),
+        }
+
+        let e = Cursor::new(self);
+        let ids = id.nodes();
+        let id_ends = id.ends();
+        let costs = e.grow_costs(ids, &id_ends, N);
+
+        let mut nodes = vec![];
+        let mut starts = vec![];
+        // The sum of the values above the node being grown
+        let mut base = C::ZERO;
+        let mut frames = vec![Frame::Visit(Some(0), 0, 0)];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(pos, j, _) if ids[j] == IdNode::One && e.is_leaf(pos) => {
+                    let (val, _, _) = e.expand(pos);
+                    let val = val
+                        .checked_add(C::ONE)
+                        .filter(|v| base.checked_add(*v).is_some())
+                        .ok_or(OverflowError)?;
+                    starts.push(nodes.len());
+                    nodes.push(Node::Leaf(val));
+                }
+                Frame::Visit(pos, j, at) if ids[j] == IdNode::SubTree => {
+                    let (val, el, er) = e.expand(pos);
+                    base = base.saturating_add(val);
+                    frames.push(Frame::Close(nodes.len()));
+                    nodes.push(Node::SubTree(val));
+
+                    let (il, ir) = (j + 1, id_ends[j + 1]);
+                    let (l, r) = (at + 1, at + 1 + costs[at + 1].1);
+                    let left = match (ids[il], ids[ir]) {
+                        (IdNode::Zero, _) => false,
+                        (_, IdNode::Zero) => true,
+                        _ => costs[l].0 < costs[r].0,
+                    };
+                    if left {
+                        frames.push(Frame::Copy(er));
+                        frames.push(Frame::Visit(el, il, l));
+                    } else {
+                        frames.push(Frame::Visit(er, ir, r));
+                        frames.push(Frame::Copy(el));
+                    }
+                }
+                Frame::Visit(..) => unreachable!(),
+                Frame::Copy(pos) => {
+                    starts.push(nodes.len());
+                    match pos {
+                        Some(i) => nodes.extend_from_slice(e.subtree(i)),
+                        None => nodes.push(Node::Leaf(C::ZERO)),
+                    }
+                }
+                Frame::Close(p) => Self::close_last(&mut nodes, &mut starts, p),
+            }
+        }
+        Ok(Self { nodes })
+    }
+
+    fn depth(&self) -> u64 {
+        let e = Cursor::new(self);
+        let mut depth = 0;
+        let mut stack = vec![(0, 1)];
+        while let Some((i, at)) = stack.pop() {
+            depth = depth.max(at);
+            if let Node::SubTree(_) = e.nodes[i] {
+                stack.push((i + 1, at + 1));
+                stack.push((e.ends[i + 1], at + 1));
+            }
+        }
+        depth
+    }
+
+    /// Copies a subtree, lifting its root by `m`.
+    fn push_lifted(nodes: &mut Vec<Node<C>>, subtree: &[Node<C>], m: C) {
+        let start = nodes.len();
+        nodes.extend_from_slice(subtree);
+        let val = nodes[start].value_mut();
+        *val = val.saturating_add(m);
+    }
+
+    /// Normalizes the node at `p` once both of its children have been written, recording where
+    /// it starts in place of the children in `starts`.
+    fn close_last(nodes: &mut Vec<Node<C>>, starts: &mut Vec<usize>, p: usize) {
+        let r = starts.pop().expect("Right child is written last");
+        starts.pop().expect("Left child is written first");
+        Self::close(nodes, p, r);
+        starts.push(p);
+    }
+
+    /// Normalizes the node at `p` whose right child starts at `r`, assuming both children are
+    /// already normalized.
+    fn close(nodes: &mut Vec<Node<C>>, p: usize, r: usize) {
+        let val = nodes[p].value();
+        match (nodes[p + 1], nodes[r]) {
+            (Node::Leaf(m0), Node::Leaf(m1)) if m0 == m1 => {
+                nodes.truncate(p);
+                nodes.push(Node::Leaf(val.saturating_add(m0)));
+            }
+            (l, r_node) => {
+                let m = l.value().min(r_node.value());
+                *nodes[p].value_mut() = val.saturating_add(m);
+                *nodes[p + 1].value_mut() -= m;
+                *nodes[r].value_mut() -= m;
+            }
+        }
+    }
+}
+
+impl<C: Counter> Cursor<'_, C> {
+    /// The cost of growing each subtree for its part of the id, as in [`EventTree::event`], in
+    /// preorder along with the number of entries each spans.
+    #[allow(non_snake_case)]
+    fn grow_costs(&self, ids: &[IdNode], id_ends: &[usize], N: u64) -> Vec<(u64, usize)> {
+        enum Frame {
+            Visit(Pos, usize),
+            /// Take the cheaper of the two children of the entry, then add.
+            Min(usize, u64),
+        }
+
+        let mut frames = vec![Frame::Visit(Some(0), 0)];
+        let mut costs: Vec<(u64, usize)> = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                // Never grow into a region we do not own
+                Frame::Visit(_, j) if ids[j] == IdNode::Zero => costs.push((u64::MAX, 1)),
+                Frame::Visit(pos, j) if ids[j] == IdNode::One => {
+                    debug_assert!(self.is_leaf(pos));
+                    costs.push((0, 1));
+                }
+                Frame::Visit(pos, j) => {
+                    // A leaf has to be expanded first, into two zero leaves
+                    let (el, er, extra) = if self.is_leaf(pos) {
+                        (None, None, N.saturating_add(1))
+                    } else {
+                        let (_, el, er) = self.expand(pos);
+                        (el, er, 1)
+                    };
+                    frames.push(Frame::Min(costs.len(), extra));
+                    costs.push((0, 0));
+                    frames.push(Frame::Visit(er, id_ends[j + 1]));
+                    frames.push(Frame::Visit(el, j + 1));
+                }
+                Frame::Min(at, extra) => {
+                    let l = costs[at + 1].0;
+                    let r = costs[at + 1 + costs[at + 1].1].0;
+                    costs[at] = (l.min(r).saturating_add(extra), costs.len() - at);
+                }
+            }
+        }
+        costs
+    }
+}
+
+impl<C: Counter> Default for FlatEventTree<C> {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::Leaf(C::ZERO)],
+        }
+    }
+}
+
+impl<C: Counter> PartialOrd for FlatEventTree<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.relation(other).into()
+    }
+}
+
+impl<C: Counter> From<&EventTree<C>> for FlatEventTree<C> {
+    fn from(event: &EventTree<C>) -> Self {
+        let mut nodes = vec![];
+        let mut starts = vec![];
+        let mut frames = vec![Some(event)];
+        let mut parents = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(EventTree::Leaf(val)) => {
+                    starts.push(nodes.len());
+                    nodes.push(Node::Leaf(*val));
+                }
+                Some(EventTree::SubTree(val, l, r)) => {
+                    parents.push(nodes.len());
+                    nodes.push(Node::SubTree(*val));
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let p = parents.pop().expect("Every close has a parent");
+                    Self::close_last(&mut nodes, &mut starts, p);
+                }
+            }
+        }
+        Self { nodes }
+    }
+}
+
+impl<C: Counter> From<EventTree<C>> for FlatEventTree<C> {
+    fn from(event: EventTree<C>) -> Self {
+        let flat = Self::from(&event);
+        event.drop_iteratively();
+        flat
+    }
+}
+
+impl<C: Counter> From<&FlatEventTree<C>> for EventTree<C> {
+    fn from(event: &FlatEventTree<C>) -> Self {
+        // Walking backwards completes the right and then the left child before their parent
+        let mut done = vec![];
+        for node in event.nodes.iter().rev() {
+            let event = match *node {
+                Node::Leaf(val) => EventTree::Leaf(val),
+                Node::SubTree(val) => {
+                    let l = done.pop().expect("Left child precedes its parent");
+                    let r = done.pop().expect("Right child precedes its parent");
+                    EventTree::SubTree(val, Box::new(l), Box::new(r))
+                }
+            };
+            done.push(event);
+        }
+        done.pop().expect("The root is always built")
+    }
+}
+
+impl<C: Counter> From<FlatEventTree<C>> for EventTree<C> {
+    fn from(event: FlatEventTree<C>) -> Self {
+        Self::from(&event)
+    }
+}
+
+impl<C: Counter> std::fmt::Display for FlatEventTree<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let e = Cursor::new(self);
+
+        enum Token {
+            Event(usize),
+            Text(&'static str),
+        }
+
+        let mut stack = vec![Token::Event(0)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Event(i) => match e.nodes[i] {
+                    Node::Leaf(val) => write!(f, "{val}")?,
+                    Node::SubTree(val) => {
+                        write!(f, "({val}, ")?;
+                        stack.push(Token::Text(")"));
+                        stack.push(Token::Event(e.ends[i + 1]));
+                        stack.push(Token::Text(", "));
+                        stack.push(Token::Event(i + 1));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays the same events and joins on both representations, checking they agree.
+    fn stamps() -> Vec<(EventTree, FlatEventTree)> {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let (a, d) = a.fork();
+        // Owning both halves makes growing weigh one side against the other
+        let ac = a.clone().join(c.clone());
+        let ids = [a, b, c, d, ac];
+        let flat_ids: Vec<_> = ids.iter().map(FlatIdTree::from).collect();
+
+        let mut stamps = vec![(EventTree::new(), FlatEventTree::new())];
+        let mut e: EventTree = EventTree::new();
+        let mut flat = FlatEventTree::new();
+        for step in 0..24 {
+            let k = (step * 7 + step / 3) % ids.len();
+            e.event_in_place(&ids[k]);
+            flat = flat.event(&flat_ids[k]);
+            assert_eq!(
+                flat.to_string(),
+                e.to_string(),
+                "Event {step} for {}",
+                ids[k]
+            );
+
+            if step % 5 == 4 {
+                let (other, other_flat) = &stamps[step % stamps.len()];
+                e = e.join(other.clone());
+                flat = flat.join(other_flat);
+                assert_eq!(flat.to_string(), e.to_string(), "Join {step}");
+            }
+            stamps.push((e.clone(), flat.clone()));
+        }
+        stamps
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for (e, flat) in stamps() {
+            assert_eq!(FlatEventTree::from(&e), flat);
+            assert_eq!(EventTree::from(&flat), e);
+        }
+
+        let unnormalized: EventTree = EventTree::SubTree(
+            1,
+            Box::new(EventTree::SubTree(
+                0,
+                Box::new(EventTree::Leaf(2)),
+                Box::new(EventTree::Leaf(2)),
+            )),
+            Box::new(EventTree::Leaf(3)),
+        );
+        assert_eq!(FlatEventTree::from(unnormalized).to_string(), "(3, 0, 1)");
+    }
+
+    #[test]
+    fn test_binary_operations() {
+        let stamps = stamps();
+        for (a, flat_a) in &stamps {
+            for (b, flat_b) in &stamps {
+                assert_eq!(flat_a.join(flat_b).to_string(), a.join_ref(b).to_string());
+                assert_eq!(flat_a.diff(flat_b).to_string(), a.diff(b).to_string());
+                assert_eq!(flat_a.mask(flat_b).to_string(), a.mask(b).to_string());
+                assert_eq!(flat_a.relation(flat_b), a.relation(b));
+                assert_eq!(flat_a.partial_cmp(flat_b), a.partial_cmp(b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_deep_grow() {
+        use EventTree::*;
+
+        // Growing chooses between two owned children at every level, all the way to the bottom
+        const DEPTH: usize = 20_000;
+        let mut id = IdTree::One;
+        let mut e: EventTree = Leaf(5);
+        let mut expected: EventTree = Leaf(6);
+        for _ in 0..DEPTH {
+            let right = IdTree::SubTree(Box::new(IdTree::One), Box::new(IdTree::Zero));
+            id = IdTree::SubTree(Box::new(id), Box::new(right));
+            e = SubTree(0, Box::new(e), Box::new(Leaf(0)));
+            expected = SubTree(0, Box::new(expected), Box::new(Leaf(0)));
+        }
+
+        let grown = FlatEventTree::from(&e).event(&FlatIdTree::from(&id));
+        assert_eq!(grown, FlatEventTree::from(&expected));
+        for e in [e, expected] {
+            e.drop_iteratively();
+        }
+        id.drop_iteratively();
+    }
+
+    #[test]
+    fn test_event_errors() {
+        let e = FlatEventTree::<u8>::from(EventTree::Leaf(u8::MAX));
+        assert_eq!(
+            e.clone().try_event(&FlatIdTree::zero()),
+            Err(EventError::AnonymousId)
+        );
+        assert_eq!(
+            e.try_event(&FlatIdTree::one()),
+            Err(EventError::Overflow(OverflowError))
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::Counter;
+
+/// Decides where an event grows the tree once it cannot be filled.
+///
+/// Growing walks down the region owned by the id to a single leaf and increments it. Wherever
+/// the id owns both children the walk can go either way, and it takes the side whose path costs
+/// less in total, going right on ties. A path costs the sum of [`GrowthStrategy::step_cost`] for
+/// every level it descends, plus [`GrowthStrategy::increment_cost`] for the leaf at its end.
+///
+/// Every strategy records the same events, so comparisons between stamps are unaffected. Only
+/// the shape and size of the resulting trees differ.
+pub trait GrowthStrategy<C: Counter> {
+    /// The cost of descending one level into a tree of depth `depth`. `expanded` holds the value
+    /// of a leaf which has to be expanded into a node first, or `None` if there is a node there.
+    fn step_cost(&self, expanded: Option<C>, depth: u64) -> u64;
+
+    /// The cost of incrementing a leaf holding `value`, relative to its parent.
+    fn increment_cost(&self, value: C) -> u64 {
+        let _ = value;
+        0
+    }
+
+    /// Whether further events under an id keep growing the leaf chosen for the first, which lets
+    /// [`EventTree::event_n`](crate::EventTree::event_n) grow it by all of them at once. This
+    /// holds when [`GrowthStrategy::increment_cost`] does not depend on the value.
+    fn grows_same_leaf(&self) -> bool {
+        false
+    }
+}
+
+/// The heuristic from the original paper, used by [`EventTree::event`](crate::EventTree::event).
+///
+/// Expanding a leaf costs more than any path through existing nodes, so the tree only grows new
+/// nodes when it has to, and otherwise increments the leaf closest to the root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PaperGrowth;
+
+impl<C: Counter> GrowthStrategy<C> for PaperGrowth {
+    fn step_cost(&self, expanded: Option<C>, depth: u64) -> u64 {
+        match expanded {
+            Some(_) => depth.saturating_add(2),
+            None => 1,
+        }
+    }
+
+    fn grows_same_leaf(&self) -> bool {
+        true
+    }
+}
+
+/// Prefers growth that adds fewer bits to the compact encoding from
+/// [`EventTree::encode`](crate::EventTree::encode).
+///
+/// Paths through existing nodes are free, while expanding a leaf costs the bits of the new node.
+/// Among the leaves that can be reached without expanding, this prefers one whose counter stays
+/// within its current encoded width.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MinSizeGrowth;
+
+impl MinSizeGrowth {
+    /// The bits added by expanding a leaf: the node header and the encoding of the new leaf.
+    const EXPANSION_BITS: u64 = 7;
+}
+
+impl<C: Counter> GrowthStrategy<C> for MinSizeGrowth {
+    fn step_cost(&self, expanded: Option<C>, _depth: u64) -> u64 {
+        match expanded {
+            Some(_) => Self::EXPANSION_BITS,
+            None => 0,
+        }
+    }
+
+    fn increment_cost(&self, value: C) -> u64 {
+        let next = value.saturating_add(C::ONE);
+        counter_bits(next) - counter_bits(value)
+    }
+}
+
+/// Prefers growth that keeps the tree shallow by incrementing the shallowest leaf it can reach,
+/// even when that means expanding a leaf the paper's heuristic would have left alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MinDepthGrowth;
+
+impl<C: Counter> GrowthStrategy<C> for MinDepthGrowth {
+    fn step_cost(&self, _expanded: Option<C>, _depth: u64) -> u64 {
+        1
+    }
+
+    fn grows_same_leaf(&self) -> bool {
+        true
+    }
+}
+
+/// The number of bits `BitWriter::write_counter` uses for `n`.
+fn counter_bits<C: Counter>(n: C) -> u64 {
+    let mut n = n.to_u128();
+    let mut width = 2;
+    let mut bits = 2;
+    while 1u128.checked_shl(width).is_some_and(|bound| n >= bound) {
+        bits += 1;
+        n -= 1 << width;
+        width += 1;
+    }
+    bits + u64::from(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventTree, IdTree, Relation};
+
+    #[test]
+    fn test_counter_bits() {
+        use crate::encoding::BitWriter;
+
+        for n in [0u64, 1, 3, 4, 11, 12, 27, 28, 1 << 40, u64::MAX] {
+            let mut writer = BitWriter::new();
+            writer.write_counter(n);
+            writer.write_bit(true);
+            let bytes = writer.finish();
+            // The marker bit is the last one set
+            let last = bytes.len() as u64 * 8 - u64::from(bytes[bytes.len() - 1].trailing_zeros());
+            assert_eq!(counter_bits(n), last - 1, "Bits for {n}");
+        }
+    }
+
+    /// Replays the same history of events and syncs between replicas with `strategy`, checking
+    /// every pair of stamps against vector clocks.
+    fn check_causality<S: GrowthStrategy<u64>>(strategy: &S) {
+        const REPLICAS: usize = 5;
+
+        let ids = IdTree::One.fork_many(REPLICAS);
+        let mut stamps: Vec<EventTree> = vec![EventTree::new(); REPLICAS];
+        let mut clocks = [[0u64; REPLICAS]; REPLICAS];
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..400 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let i = seed as usize % REPLICAS;
+            let j = (seed >> 32) as usize % REPLICAS;
+
+            if seed & (1 << 20) == 0 {
+                let before = stamps[i].clone();
+                stamps[i]
+                    .try_event_in_place_with_strategy(&ids[i], Default::default(), strategy)
+                    .expect("Replicas own non-anonymous ids");
+                clocks[i][i] += 1;
+                assert_eq!(before.relation(&stamps[i]), Relation::HappenedBefore);
+                // Only the region owned by the replica changes
+                assert_eq!(before.exclude(&ids[i]), stamps[i].exclude(&ids[i]));
+            } else {
+                stamps[i] = stamps[i].clone().join(stamps[j].clone());
+                let other = clocks[j];
+                for (mine, theirs) in clocks[i].iter_mut().zip(other) {
+                    *mine = (*mine).max(theirs);
+                }
+            }
+
+            for a in 0..REPLICAS {
+                for b in 0..REPLICAS {
+                    let le = |x: &[u64], y: &[u64]| x.iter().zip(y).all(|(x, y)| x <= y);
+                    let expected = match (le(&clocks[a], &clocks[b]), le(&clocks[b], &clocks[a])) {
+                        (true, true) => Relation::Equal,
+                        (true, false) => Relation::HappenedBefore,
+                        (false, true) => Relation::HappenedAfter,
+                        (false, false) => Relation::Concurrent,
+                    };
+                    assert_eq!(stamps[a].relation(&stamps[b]), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_causality() {
+        check_causality(&PaperGrowth);
+        check_causality(&MinSizeGrowth);
+        check_causality(&MinDepthGrowth);
+    }
+
+    #[test]
+    fn test_paper_matches_original() {
+        // Stamps from the original recursive implementation for the same history
+        let expected = [
+            "(0, (3, 10, 0), 0)",
+            "(0, (0, 2, 0), (0, 6, (0, 10, 0)))",
+            "(0, (0, 8, 0), (0, 6, (0, 13, 0)))",
+            "(0, (0, 0, 7), (0, 0, (0, 0, 8)))",
+            "(0, (0, 0, 5), (2, 2, (0, 0, 12)))",
+            "(0, (3, 10, 0), 0)",
+        ];
+
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let (a, d) = a.fork();
+        let (c, e) = c.fork();
+        let ids = [a.clone(), b, c.clone(), d, e, a.join(c)];
+        let mut stamps: Vec<EventTree> = vec![EventTree::new(); ids.len()];
+        for i in 0..60usize {
+            let r = (i * 5 + i / 3) % ids.len();
+            stamps[r] = stamps[r].clone().event(&ids[r]);
+            if i % 4 == 3 {
+                let other = (r + i) % ids.len();
+                stamps[r] = stamps[r].clone().join(stamps[other].clone());
+            }
+        }
+
+        let stamps: Vec<_> = stamps.iter().map(|e| e.to_string()).collect();
+        assert_eq!(stamps, expected);
+    }
+
+    #[test]
+    fn test_min_size() {
+        use EventTree::*;
+
+        let id = IdTree::subtree(
+            IdTree::subtree(IdTree::One, IdTree::Zero),
+            IdTree::subtree(IdTree::Zero, IdTree::subtree(IdTree::Zero, IdTree::One)),
+        );
+        let stamp: EventTree = EventTree::subtree(
+            0,
+            EventTree::subtree(1, Leaf(59), Leaf(0)),
+            EventTree::subtree(0, Leaf(0), EventTree::subtree(0, Leaf(0), Leaf(1))),
+        );
+
+        // Incrementing the shallow leaf needs a wider counter, so it grows the deep one instead
+        let paper = stamp.clone().event_with_strategy(&id, &PaperGrowth);
+        let small = stamp.clone().event_with_strategy(&id, &MinSizeGrowth);
+        assert_eq!(paper.to_string(), "(0, (1, 60, 0), (0, 0, (0, 0, 1)))");
+        assert_eq!(small.to_string(), "(0, (1, 59, 0), (0, 0, (0, 0, 2)))");
+        assert!(small.encode().len() < paper.encode().len());
+    }
+
+    #[test]
+    fn test_min_depth() {
+        use EventTree::*;
+
+        let id = IdTree::subtree(
+            IdTree::subtree(IdTree::One, IdTree::Zero),
+            IdTree::subtree(IdTree::Zero, IdTree::subtree(IdTree::Zero, IdTree::One)),
+        );
+        let stamp: EventTree = EventTree::subtree(
+            0,
+            Leaf(2),
+            EventTree::subtree(0, Leaf(0), EventTree::subtree(0, Leaf(0), Leaf(1))),
+        );
+
+        // The paper grows the existing deep leaf rather than expanding the shallow one
+        let paper = stamp.clone().event_with_strategy(&id, &PaperGrowth);
+        let shallow = stamp.clone().event_with_strategy(&id, &MinDepthGrowth);
+        assert_eq!(paper.to_string(), "(0, 2, (0, 0, (0, 0, 2)))");
+        assert_eq!(shallow.to_string(), "(0, (2, 1, 0), (0, 0, (0, 0, 1)))");
+    }
+}
@@ -0,0 +1,117 @@
+use super::*;
+use crate::parser::{ParseError, ParseOptions, Parser};
+
+impl<C: Counter> EventTree<C> {
+    /// Parses the `Display` representation of an `EventTree`, e.g. `(0, (0, 1, 0), 0)`.
+    pub fn parse_with(s: &str, options: &ParseOptions) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(s, options);
+        let event = parse_event(&mut parser)?;
+        parser.finish()?;
+        Ok(event)
+    }
+}
+
+impl<C: Counter> std::str::FromStr for EventTree<C> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &ParseOptions::default())
+    }
+}
+
+/// event := counter | `(` counter `,` event `,` event `)`
+pub(crate) fn parse_event<C: Counter>(parser: &mut Parser) -> Result<EventTree<C>, ParseError> {
+    if parser.eat(b'(') {
+        parser.descend()?;
+        let val = parser.counter()?;
+        parser.expect(b',', "`,`")?;
+        let left = parse_event(parser)?;
+        parser.expect(b',', "`,`")?;
+        let right = parse_event(parser)?;
+        parser.expect(b')', "`)`")?;
+        parser.ascend();
+        return Ok(EventTree::node(val, left, right));
+    }
+
+    if parser.peek().is_some_and(|c| c.is_ascii_digit()) {
+        Ok(EventTree::Leaf(parser.counter()?))
+    } else {
+        Err(parser.unexpected("a counter or `(`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseErrorKind;
+
+    #[test]
+    fn test_parse() {
+        let strs = [
+            "0",
+            "5",
+            "(0, 1, 0)",
+            "(0, (0, 1, 0), 0)",
+            "(1, 0, (0, 1, 0))",
+            "(2, (0, (1, 0, 3), 0), (4, 0, (0, 0, 1)))",
+            "(18446744073709551614, 0, 1)",
+        ];
+
+        for s in strs {
+            let e: EventTree = s.parse().unwrap_or_else(|_| panic!("Unable to parse {s}"));
+            assert_eq!(format!("{e}"), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_whitespace() {
+        let e: EventTree = "  ( 1 ,(0,1 , 0) ,\t2 ) "
+            .parse()
+            .expect("Valid event tree");
+        assert_eq!(e.to_string(), "(1, (0, 1, 0), 2)");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = "(0, 1)".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 5);
+        assert_eq!(err.expected, "`,`");
+
+        let err = "(0, 1, 0".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedEnd);
+        assert_eq!(err.offset, 8);
+
+        let err = "(0, (1, 0, 0)), 1)".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 13);
+
+        let err = "18446744073709551616".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidValue);
+        assert_eq!(err.offset, 0);
+        assert_eq!(err.snippet, "1844674407370955");
+
+        let err = "4294967296".parse::<EventTree<u32>>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidValue);
+
+        let err = "(x, 0, 1)".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 1);
+        assert_eq!(err.expected, "a counter");
+
+        let err = "(0, 0, 1) (0, 1, 0)".parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TrailingInput);
+        assert_eq!(err.offset, 10);
+    }
+
+    #[test]
+    fn test_parse_max_depth() {
+        let deep = "(0, ".repeat(600) + "1" + &", 0)".repeat(600);
+        let err = deep.parse::<EventTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TooDeep);
+
+        let options = ParseOptions::new().max_depth(600);
+        let e: EventTree = EventTree::parse_with(&deep, &options).expect("Within the depth limit");
+        assert_eq!(e.to_string(), deep);
+    }
+}
@@ -0,0 +1,176 @@
+use super::*;
+use crate::Dyadic;
+use std::ops::{Range, RangeInclusive};
+
+impl<C: Counter> EventTree<C> {
+    /// The smallest and largest value over the region owned by `id`, such as the number of
+    /// events a replica has recorded under it, or `None` for an anonymous id.
+    pub fn value_at(&self, id: &IdTree) -> Option<RangeInclusive<C>> {
+        use EventTree::*;
+
+        let mut bounds: Option<(C, C)> = None;
+        let mut stack = vec![(self, C::ZERO, id)];
+        while let Some((e, base, id)) = stack.pop() {
+            match (e, id) {
+                (_, IdTree::Zero) => {}
+                // A leaf covers the whole id below it, which may still own nothing
+                (Leaf(_), id) if id.is_anonymous() => {}
+                (Leaf(val), _) => {
+                    let val = base.saturating_add(*val);
+                    bounds = Some(match bounds {
+                        Some((min, max)) => (min.min(val), max.max(val)),
+                        None => (val, val),
+                    });
+                }
+                (SubTree(val, l, r), IdTree::One) => {
+                    let base = base.saturating_add(*val);
+                    stack.push((r, base, id));
+                    stack.push((l, base, id));
+                }
+                (SubTree(val, l, r), IdTree::SubTree(il, ir)) => {
+                    let base = base.saturating_add(*val);
+                    stack.push((r, base, ir));
+                    stack.push((l, base, il));
+                }
+            }
+        }
+        bounds.map(|(min, max)| min..=max)
+    }
+
+    /// The dyadic ranges `[start, end)` of the leaves from left to right, each with its value.
+    pub fn regions(&self) -> impl Iterator<Item = (Range<Dyadic>, C)> + '_ {
+        use EventTree::*;
+
+        // Nodes still to visit with their base, depth and the side they hang off their parent
+        let mut stack = vec![(self, C::ZERO, 0, false)];
+        let mut path = vec![];
+        std::iter::from_fn(move || {
+            while let Some((e, base, depth, right)) = stack.pop() {
+                if depth > 0 {
+                    path.truncate(depth - 1);
+                    path.push(right);
+                }
+                match e {
+                    Leaf(val) => {
+                        return Some((Dyadic::interval(path.clone()), base.saturating_add(*val)));
+                    }
+                    SubTree(val, l, r) => {
+                        let base = base.saturating_add(*val);
+                        stack.push((r, base, depth + 1, true));
+                        stack.push((l, base, depth + 1, false));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Builds the normalized tree holding each value over its range and zero elsewhere. Where
+    /// ranges overlap the largest value wins, as if the trees for each were joined.
+    pub fn from_regions<I>(regions: I) -> Self
+    where
+        I: IntoIterator<Item = (Range<Dyadic>, C)>,
+    {
+        regions
+            .into_iter()
+            .map(|(range, val)| Self::over(&IdTree::from_intervals([range]), val))
+            .fold(Self::default(), |acc, e| acc.join(e))
+            .normalize()
+    }
+
+    /// The tree holding `val` over the region owned by `id` and zero elsewhere.
+    fn over(id: &IdTree, val: C) -> Self {
+        let mut frames = vec![Some(id)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(IdTree::Zero) => done.push(EventTree::Leaf(C::ZERO)),
+                Some(IdTree::One) => done.push(EventTree::Leaf(val)),
+                Some(IdTree::SubTree(l, r)) => {
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let (l, r) = Self::pop_pair(&mut done);
+                    done.push(Self::node(C::ZERO, l, r));
+                }
+            }
+        }
+        done.pop().expect("The root is always built")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dyadic(numerator: u128, exponent: u32) -> Dyadic {
+        Dyadic::new(numerator, exponent).expect("At most one")
+    }
+
+    #[test]
+    fn test_value_at() {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let mut e: EventTree = EventTree::new();
+        e.event_in_place(&a);
+        e.event_in_place(&a);
+        e.event_in_place(&c);
+
+        assert_eq!(e.value_at(&a), Some(2..=2));
+        assert_eq!(e.value_at(&b), Some(0..=0));
+        assert_eq!(e.value_at(&c), Some(1..=1));
+        assert_eq!(e.value_at(&b.join(c)), Some(0..=1));
+        assert_eq!(e.value_at(&IdTree::one()), Some(0..=2));
+        assert_eq!(e.value_at(&IdTree::zero()), None);
+        let anonymous = IdTree::SubTree(Box::new(IdTree::Zero), Box::new(IdTree::Zero));
+        assert_eq!(EventTree::Leaf(3u64).value_at(&anonymous), None);
+        assert_eq!(e.value_at(&anonymous), None);
+
+        let unnormalized: EventTree = EventTree::SubTree(
+            1,
+            Box::new(EventTree::Leaf(3)),
+            Box::new(EventTree::Leaf(3)),
+        );
+        assert_eq!(unnormalized.value_at(&a), Some(4..=4));
+    }
+
+    #[test]
+    fn test_regions() {
+        let e: EventTree = EventTree::subtree(
+            1,
+            EventTree::Leaf(2),
+            EventTree::subtree(0, EventTree::Leaf(0), EventTree::Leaf(4)),
+        );
+
+        let regions: Vec<_> = e.regions().collect();
+        assert_eq!(
+            regions,
+            vec![
+                (dyadic(0, 0)..dyadic(1, 1), 3),
+                (dyadic(1, 1)..dyadic(3, 2), 1),
+                (dyadic(3, 2)..Dyadic::ONE, 5),
+            ]
+        );
+        assert_eq!(EventTree::from_regions(regions), e);
+        assert_eq!(
+            EventTree::<u64>::new().regions().collect::<Vec<_>>(),
+            [(Dyadic::ZERO..Dyadic::ONE, 0)]
+        );
+    }
+
+    #[test]
+    fn test_from_regions() {
+        let e: EventTree = EventTree::from_regions([
+            (dyadic(1, 2)..dyadic(3, 2), 2),
+            (dyadic(1, 2)..Dyadic::ONE, 1),
+        ]);
+        assert_eq!(e.to_string(), "(0, (0, 0, 2), (1, 1, 0))");
+        assert!(e.is_normalized());
+
+        assert_eq!(EventTree::<u64>::from_regions([]), EventTree::new());
+        let full: EventTree = EventTree::from_regions([(Dyadic::ZERO..Dyadic::ONE, 7)]);
+        assert_eq!(full.to_string(), "7");
+    }
+}
@@ -0,0 +1,184 @@
+//! Serde support with the same representation as a derived implementation, but refusing trees
+//! nested deeper than [`MAX_DECODE_DEPTH`](crate::MAX_DECODE_DEPTH) on the way in, so that
+//! untrusted input cannot overflow the stack. Use [`EventTree::deserialize_with`] to accept
+//! deeper trees.
+//!
+//! Serializing has no limit, so that every tree the crate builds can be written out, but the
+//! serializer nests once per level just as a derived implementation would.
+use super::*;
+use crate::DecodeOptions;
+use serde::de::{self, DeserializeSeed, EnumAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::SerializeTupleVariant;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+const VARIANTS: &[&str] = &["Leaf", "SubTree"];
+const TOO_DEEP: &str = "Maximum nesting depth exceeded";
+
+impl<C: Counter + Serialize> Serialize for EventTree<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            EventTree::Leaf(val) => {
+                serializer.serialize_newtype_variant("EventTree", 0, "Leaf", val)
+            }
+            EventTree::SubTree(val, l, r) => {
+                let mut s = serializer.serialize_tuple_variant("EventTree", 1, "SubTree", 3)?;
+                s.serialize_field(val)?;
+                s.serialize_field(l)?;
+                s.serialize_field(r)?;
+                s.end()
+            }
+        }
+    }
+}
+
+impl<'de, C: Counter + Deserialize<'de>> Deserialize<'de> for EventTree<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::deserialize_with(deserializer, &DecodeOptions::default())
+    }
+}
+
+impl<C: Counter> EventTree<C> {
+    /// Deserializes a tree nested at most [`DecodeOptions::max_depth`] deep. Deserializing
+    /// recurses once per level, so the limit should stay well within the stack.
+    pub fn deserialize_with<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        options: &DecodeOptions,
+    ) -> Result<Self, D::Error>
+    where
+        C: Deserialize<'de>,
+    {
+        Seed {
+            depth: 0,
+            max_depth: options.max_depth,
+            counter: PhantomData,
+        }
+        .deserialize(deserializer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(variant_identifier)]
+enum Variant {
+    Leaf,
+    SubTree,
+}
+
+/// Deserializes a subtree with `depth` subtrees above it.
+struct Seed<C> {
+    depth: usize,
+    max_depth: usize,
+    counter: PhantomData<C>,
+}
+
+impl<C> Clone for Seed<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for Seed<C> {}
+
+impl<'de, C: Counter + Deserialize<'de>> DeserializeSeed<'de> for Seed<C> {
+    type Value = EventTree<C>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_enum("EventTree", VARIANTS, self)
+    }
+}
+
+impl<'de, C: Counter + Deserialize<'de>> Visitor<'de> for Seed<C> {
+    type Value = EventTree<C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("enum EventTree")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        match data.variant()? {
+            (Variant::Leaf, variant) => variant.newtype_variant().map(EventTree::Leaf),
+            (Variant::SubTree, _) if self.depth >= self.max_depth => {
+                Err(de::Error::custom(TOO_DEEP))
+            }
+            (Variant::SubTree, variant) => variant.tuple_variant(
+                3,
+                SubTreeVisitor(Seed {
+                    depth: self.depth + 1,
+                    ..self
+                }),
+            ),
+        }
+    }
+}
+
+/// Deserializes the value and children of a subtree with `seed` for each child.
+struct SubTreeVisitor<C>(Seed<C>);
+
+impl<'de, C: Counter + Deserialize<'de>> Visitor<'de> for SubTreeVisitor<C> {
+    type Value = EventTree<C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("tuple variant EventTree::SubTree")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let val = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let left = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let right = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        Ok(EventTree::node(val, left, right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_DECODE_DEPTH;
+    use bincode::Options;
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let e: EventTree = "(1, (0, 2, 0), 3)".parse().expect("Valid event tree");
+        let bytes = bincode::serialize(&e).expect("Shallow tree");
+        let decoded: EventTree = bincode::deserialize(&bytes).expect("Valid event tree");
+        assert_eq!(decoded.to_string(), e.to_string());
+    }
+
+    #[test]
+    fn test_serde_deep() {
+        // A stamp grown under an id forked more often than the default limit still serializes
+        let mut id = IdTree::one();
+        for _ in 0..=MAX_DECODE_DEPTH {
+            id = id.fork().1;
+        }
+        let e: EventTree = EventTree::new().event(&id);
+        let bytes = bincode::serialize(&e).expect("Any tree serializes");
+        assert!(bincode::deserialize::<EventTree>(&bytes).is_err());
+
+        let bincode = bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let options = DecodeOptions::new().max_depth(MAX_DECODE_DEPTH + 1);
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, bincode);
+        let decoded =
+            EventTree::deserialize_with(&mut deserializer, &options).expect("Deep enough");
+        assert_eq!(decoded, e);
+    }
+
+    #[test]
+    fn test_serde_too_deep() {
+        // A crafted chain of subtrees far deeper than the stack could recurse through
+        let mut bytes = vec![];
+        for _ in 0..100_000 {
+            bytes.extend(1u32.to_le_bytes());
+            bytes.extend(0u64.to_le_bytes());
+        }
+        assert!(bincode::deserialize::<EventTree>(&bytes).is_err());
+    }
+}
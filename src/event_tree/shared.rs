@@ -0,0 +1,533 @@
+use super::*;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Weak};
+
+/// An [`EventTree`] whose subtrees are reference counted, so that a clone is a single counter
+/// increment and stamps can share whatever subtrees they have in common.
+///
+/// Trees built through the same [`EventInterner`] are hash-consed: equal subtrees are the same
+/// allocation, and comparing them is a pointer check. Always kept normalized.
+#[derive(Clone)]
+pub struct SharedEventTree<C: Counter = u64>(Arc<Node<C>>);
+
+struct Node<C: Counter> {
+    /// Hash of the whole subtree, computed once when the node is built.
+    hash: u64,
+    value: C,
+    /// Both children, or neither for a leaf. Only taken while the node is being dropped.
+    children: Option<[SharedEventTree<C>; 2]>,
+}
+
+impl<C: Counter> Node<C> {
+    fn new(value: C, children: Option<[SharedEventTree<C>; 2]>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        if let Some([l, r]) = &children {
+            hasher.write_u64(l.0.hash);
+            hasher.write_u64(r.0.hash);
+        }
+        Self {
+            hash: hasher.finish(),
+            value,
+            children,
+        }
+    }
+}
+
+/// Unlinks subtrees one at a time so that dropping the last reference to a deep timestamp cannot
+/// overflow the stack. Subtrees still shared elsewhere are left alone.
+impl<C: Counter> Drop for Node<C> {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        self.detach_unique(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.detach_unique(&mut stack);
+        }
+    }
+}
+
+impl<C: Counter> Node<C> {
+    fn detach_unique(&mut self, stack: &mut Vec<Self>) {
+        for child in self.children.take().into_iter().flatten() {
+            if let Some(node) = Arc::into_inner(child.0) {
+                stack.push(node);
+            }
+        }
+    }
+}
+
+impl<C: Counter> SharedEventTree<C> {
+    pub fn leaf(value: C) -> Self {
+        Self(Arc::new(Node::new(value, None)))
+    }
+
+    /// Whether both are the very same allocation, which is implied by equality for trees built
+    /// through the same [`EventInterner`].
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Joins two timestamps, reusing every subtree of either side which survives unchanged.
+    pub fn join(&self, other: &Self) -> Self {
+        let mut frames = vec![Some(((self, C::ZERO), (other, C::ZERO)))];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some(((a, a_base), (b, b_base))) = frame else {
+                let r = done.pop().expect("Right child is joined last");
+                let l = done.pop().expect("Left child is joined first");
+                done.push(Self::node(C::ZERO, l, r));
+                continue;
+            };
+
+            let (x, y) = (
+                a_base.saturating_add(a.value()),
+                b_base.saturating_add(b.value()),
+            );
+            let joined = match (&a.0.children, &b.0.children) {
+                _ if a.ptr_eq(b) && a_base == b_base => Some(a.lifted(a_base)),
+                (None, None) => Some(Self::leaf(x.max(y))),
+                (None, Some(_)) if x <= y => Some(b.lifted(b_base)),
+                (Some(_), None) if y <= x => Some(a.lifted(a_base)),
+                _ => None,
+            };
+
+            match joined {
+                Some(joined) => done.push(joined),
+                None => {
+                    let [al, ar] = a.split(a_base);
+                    let [bl, br] = b.split(b_base);
+                    frames.push(None);
+                    frames.push(Some((ar, br)));
+                    frames.push(Some((al, bl)));
+                }
+            }
+        }
+        done.pop().expect("The root is always joined")
+    }
+
+    /// Computes the causal relation to `other`, skipping any subtree the two share.
+    pub fn relation(&self, other: &Self) -> Relation {
+        // Cleared whenever a point is found where `self` is greater or lesser than `other`
+        let mut le = true;
+        let mut ge = true;
+
+        let mut stack = vec![((self, C::ZERO), (other, C::ZERO))];
+        while let Some(((a, a_base), (b, b_base))) = stack.pop() {
+            if a.ptr_eq(b) && a_base == b_base {
+                continue;
+            }
+
+            if let (None, None) = (&a.0.children, &b.0.children) {
+                let x = a_base.saturating_add(a.value());
+                match x.cmp(&b_base.saturating_add(b.value())) {
+                    Ordering::Less => ge = false,
+                    Ordering::Greater => le = false,
+                    Ordering::Equal => {}
+                }
+                if !le && !ge {
+                    break;
+                }
+            } else {
+                let [al, ar] = a.split(a_base);
+                let [bl, br] = b.split(b_base);
+                stack.push((ar, br));
+                stack.push((al, bl));
+            }
+        }
+
+        match (le, ge) {
+            (true, true) => Relation::Equal,
+            (true, false) => Relation::HappenedBefore,
+            (false, true) => Relation::HappenedAfter,
+            (false, false) => Relation::Concurrent,
+        }
+    }
+
+    fn value(&self) -> C {
+        self.0.value
+    }
+
+    /// The children of the tree along with their base. A leaf stands in for both of its own
+    /// children.
+    fn split(&self, base: C) -> [(&Self, C); 2] {
+        match &self.0.children {
+            None => [(self, base); 2],
+            Some([l, r]) => {
+                let base = base.saturating_add(self.value());
+                [(l, base), (r, base)]
+            }
+        }
+    }
+
+    /// The tree with its root value changed to `value`, sharing the children.
+    fn with_value(&self, value: C) -> Self {
+        if value == self.value() {
+            self.clone()
+        } else {
+            Self(Arc::new(Node::new(value, self.0.children.clone())))
+        }
+    }
+
+    fn lifted(&self, m: C) -> Self {
+        self.with_value(self.value().saturating_add(m))
+    }
+
+    /// Builds a node over already normalized children, hoisting their common minimum.
+    fn node(val: C, l: Self, r: Self) -> Self {
+        if l.0.children.is_none() && r.0.children.is_none() && l.value() == r.value() {
+            return Self::leaf(val.saturating_add(l.value()));
+        }
+
+        let m = l.value().min(r.value());
+        let children = [l.with_value(l.value() - m), r.with_value(r.value() - m)];
+        Self(Arc::new(Node::new(val.saturating_add(m), Some(children))))
+    }
+}
+
+impl<C: Counter> From<&EventTree<C>> for SharedEventTree<C> {
+    /// Shares the identical subtrees within `event`; use an [`EventInterner`] to share them
+    /// across many timestamps.
+    fn from(event: &EventTree<C>) -> Self {
+        EventInterner::default().intern(event)
+    }
+}
+
+impl<C: Counter> From<EventTree<C>> for SharedEventTree<C> {
+    fn from(event: EventTree<C>) -> Self {
+        let shared = Self::from(&event);
+        event.drop_iteratively();
+        shared
+    }
+}
+
+impl<C: Counter> From<&SharedEventTree<C>> for EventTree<C> {
+    fn from(event: &SharedEventTree<C>) -> Self {
+        let mut frames = vec![Some(event)];
+        let mut values = vec![];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(e) => match &e.0.children {
+                    None => done.push(EventTree::Leaf(e.value())),
+                    Some([l, r]) => {
+                        values.push(e.value());
+                        frames.push(None);
+                        frames.push(Some(r));
+                        frames.push(Some(l));
+                    }
+                },
+                None => {
+                    let val = values.pop().expect("Every node frame has a value");
+                    let (l, r) = EventTree::pop_pair(&mut done);
+                    done.push(EventTree::SubTree(val, Box::new(l), Box::new(r)));
+                }
+            }
+        }
+        done.pop().expect("The root is always built")
+    }
+}
+
+impl<C: Counter> From<SharedEventTree<C>> for EventTree<C> {
+    fn from(event: SharedEventTree<C>) -> Self {
+        Self::from(&event)
+    }
+}
+
+impl<C: Counter> Default for SharedEventTree<C> {
+    fn default() -> Self {
+        Self::leaf(C::ZERO)
+    }
+}
+
+impl<C: Counter> PartialOrd for SharedEventTree<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.relation(other).into()
+    }
+}
+
+/// Trees are compared structurally, stopping early at shared subtrees and mismatched hashes.
+impl<C: Counter> PartialEq for SharedEventTree<C> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if a.ptr_eq(b) {
+                continue;
+            }
+            if a.0.hash != b.0.hash || a.value() != b.value() {
+                return false;
+            }
+            match (&a.0.children, &b.0.children) {
+                (None, None) => {}
+                (Some([al, ar]), Some([bl, br])) => {
+                    stack.push((ar, br));
+                    stack.push((al, bl));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl<C: Counter> Eq for SharedEventTree<C> {}
+
+impl<C: Counter> Hash for SharedEventTree<C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl<C: Counter> std::fmt::Debug for SharedEventTree<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "SharedEventTree({self})")
+    }
+}
+
+impl<C: Counter> std::fmt::Display for SharedEventTree<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        enum Token<'a, C: Counter> {
+            Event(&'a SharedEventTree<C>),
+            Text(&'static str),
+        }
+
+        let mut stack = vec![Token::Event(self)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Event(e) => match &e.0.children {
+                    None => write!(f, "{}", e.value())?,
+                    Some([l, r]) => {
+                        write!(f, "({}, ", e.value())?;
+                        stack.push(Token::Text(")"));
+                        stack.push(Token::Event(r));
+                        stack.push(Token::Text(", "));
+                        stack.push(Token::Event(l));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A hash-consing table which hands out a single shared allocation for every distinct subtree.
+///
+/// Only weak references are held, so subtrees are freed once no timestamp uses them anymore.
+pub struct EventInterner<C: Counter = u64> {
+    nodes: HashMap<Key<C>, Weak<Node<C>>>,
+    /// Table size at which dead entries are next purged.
+    purge_at: usize,
+}
+
+/// A node is identified by its value and the allocations of its interned children.
+type Key<C> = (C, Option<[usize; 2]>);
+
+impl EventInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Counter> EventInterner<C> {
+    /// Converts `event`, reusing every subtree already interned.
+    pub fn intern(&mut self, event: &EventTree<C>) -> SharedEventTree<C> {
+        if !event.is_normalized() {
+            let normalized = event.clone().normalize();
+            let shared = self.intern(&normalized);
+            normalized.drop_iteratively();
+            return shared;
+        }
+
+        self.intern_with(event, |e| match e {
+            EventTree::Leaf(val) => (*val, None),
+            EventTree::SubTree(val, l, r) => (*val, Some([l.as_ref(), r.as_ref()])),
+        })
+    }
+
+    /// Re-interns a tree built elsewhere, such as the result of [`SharedEventTree::join`].
+    pub fn intern_shared(&mut self, event: &SharedEventTree<C>) -> SharedEventTree<C> {
+        self.intern_with(event, |e| {
+            (e.value(), e.0.children.as_ref().map(|[l, r]| [l, r]))
+        })
+    }
+
+    /// Interns a tree bottom-up, where `node` gives the value and children of each of its nodes.
+    fn intern_with<T: Copy>(
+        &mut self,
+        root: T,
+        node: impl Fn(T) -> (C, Option<[T; 2]>),
+    ) -> SharedEventTree<C> {
+        let mut frames = vec![Some(root)];
+        let mut values = vec![];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(e) => match node(e) {
+                    (val, None) => done.push(self.get_or_insert(val, None)),
+                    (val, Some([l, r])) => {
+                        values.push(val);
+                        frames.push(None);
+                        frames.push(Some(r));
+                        frames.push(Some(l));
+                    }
+                },
+                None => {
+                    let val = values.pop().expect("Every node frame has a value");
+                    let r = done.pop().expect("Right child is interned last");
+                    let l = done.pop().expect("Left child is interned first");
+                    done.push(self.get_or_insert(val, Some([l, r])));
+                }
+            }
+        }
+        done.pop().expect("The root is always interned")
+    }
+
+    /// The number of distinct subtrees which are still alive.
+    pub fn len(&self) -> usize {
+        self.nodes
+            .values()
+            .filter(|node| node.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the subtrees which are no longer used by any timestamp.
+    pub fn purge(&mut self) {
+        self.nodes.retain(|_, node| node.strong_count() > 0);
+        self.purge_at = (self.nodes.len() * 2).max(Self::MIN_PURGE);
+    }
+
+    const MIN_PURGE: usize = 1024;
+
+    fn get_or_insert(
+        &mut self,
+        value: C,
+        children: Option<[SharedEventTree<C>; 2]>,
+    ) -> SharedEventTree<C> {
+        let key = (
+            value,
+            children
+                .as_ref()
+                .map(|[l, r]| [Arc::as_ptr(&l.0) as usize, Arc::as_ptr(&r.0) as usize]),
+        );
+        // A live entry keeps its children alive, so their addresses cannot have been reused
+        if let Some(node) = self.nodes.get(&key).and_then(Weak::upgrade) {
+            return SharedEventTree(node);
+        }
+
+        if self.nodes.len() >= self.purge_at {
+            self.purge();
+        }
+        let node = Arc::new(Node::new(value, children));
+        self.nodes.insert(key, Arc::downgrade(&node));
+        SharedEventTree(node)
+    }
+}
+
+impl<C: Counter> Default for EventInterner<C> {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            purge_at: Self::MIN_PURGE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamps() -> Vec<EventTree> {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let ids = [a, b, c];
+
+        let mut e: EventTree = EventTree::new();
+        let mut stamps = vec![e.clone()];
+        for step in 0..12 {
+            e.event_in_place(&ids[(step * 5 + step / 2) % ids.len()]);
+            stamps.push(e.clone());
+        }
+        stamps
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for e in stamps() {
+            let shared = SharedEventTree::from(&e);
+            assert_eq!(shared.to_string(), e.to_string());
+            assert_eq!(EventTree::from(&shared), e);
+        }
+
+        let unnormalized: EventTree = EventTree::SubTree(
+            0,
+            Box::new(EventTree::Leaf(2)),
+            Box::new(EventTree::Leaf(2)),
+        );
+        assert_eq!(SharedEventTree::from(unnormalized).to_string(), "2");
+    }
+
+    #[test]
+    fn test_interning() {
+        let mut interner = EventInterner::new();
+        let stamps = stamps();
+        let shared: Vec<_> = stamps.iter().map(|e| interner.intern(e)).collect();
+
+        // Equal trees are the same allocation, and so are equal subtrees across stamps
+        let copy = interner.intern(&stamps[5].clone());
+        assert!(copy.ptr_eq(&shared[5]));
+        assert!(copy.clone().ptr_eq(&copy));
+        let total: usize = stamps
+            .iter()
+            .map(|e| e.to_string().matches('(').count() * 2 + 1)
+            .sum();
+        assert!(interner.len() < total);
+
+        let joined = interner.intern_shared(&shared[3].join(&shared[7]));
+        assert!(joined.ptr_eq(&interner.intern(&stamps[3].join_ref(&stamps[7]))));
+
+        drop(shared);
+        drop(copy);
+        drop(joined);
+        interner.purge();
+        assert!(interner.is_empty());
+    }
+
+    #[test]
+    fn test_operations() {
+        let stamps = stamps();
+        let mut interner = EventInterner::new();
+        for a in &stamps {
+            for b in &stamps {
+                let (sa, sb) = (interner.intern(a), interner.intern(b));
+                assert_eq!(sa.join(&sb).to_string(), a.join_ref(b).to_string());
+                assert_eq!(sa.relation(&sb), a.relation(b));
+                assert_eq!(sa == sb, a == b);
+                assert_eq!(sa == SharedEventTree::from(b), a == b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        let mut e: EventTree = EventTree::Leaf(0);
+        for _ in 0..50_000 {
+            e = EventTree::SubTree(0, Box::new(EventTree::Leaf(1)), Box::new(e));
+        }
+
+        let mut interner = EventInterner::new();
+        let shared = interner.intern(&e);
+        assert_eq!(shared, SharedEventTree::from(&e));
+        assert_eq!(shared.join(&shared).relation(&shared), Relation::Equal);
+        let copy = EventTree::from(&shared);
+        assert_eq!(copy, e);
+
+        // Shared nodes drop iteratively on their own, unlike boxed trees
+        copy.drop_iteratively();
+        e.drop_iteratively();
+    }
+}
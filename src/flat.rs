@@ -0,0 +1,27 @@
+//! The preorder layout shared by [`FlatEventTree`](crate::FlatEventTree) and
+//! [`FlatIdTree`](crate::FlatIdTree): every subtree node is immediately followed by its left and
+//! then its right subtree, so a whole tree lives in a single allocation.
+
+/// For every node, the index just past the end of its subtree. The right child of a subtree node
+/// at `i` therefore starts at `ends[i + 1]`.
+pub(crate) fn subtree_ends(len: usize, is_leaf: impl Fn(usize) -> bool) -> Vec<usize> {
+    let mut ends = vec![0; len];
+    // Both children of a node come after it, so walking backwards visits them first
+    for i in (0..len).rev() {
+        ends[i] = if is_leaf(i) { i + 1 } else { ends[ends[i + 1]] };
+    }
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subtree_ends() {
+        // (x, (x, x, x), x) in preorder
+        let leaves = [false, false, true, true, true];
+        let ends = subtree_ends(leaves.len(), |i| leaves[i]);
+        assert_eq!(ends, vec![5, 4, 3, 4, 5]);
+    }
+}
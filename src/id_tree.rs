@@ -1,10 +1,31 @@
-use std::collections::VecDeque;
+use thiserror::Error;
 
+mod defrag;
+mod encoding;
+pub(crate) mod flat;
+mod interval;
 #[cfg(feature = "parse")]
-mod parser;
+pub(crate) mod parser;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod small;
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub use flat::FlatIdTree;
+pub use interval::Dyadic;
+pub use small::SmallIdTree;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Cannot join overlapping ids")]
+pub struct OverlapError {
+    /// The part of the interval owned by both ids.
+    pub overlap: IdTree,
+}
+
+/// The part of the interval owned by a replica, as in the original paper.
+///
+/// Dropping an id recurses once per level, so an id many thousands of levels deep can overflow
+/// the stack when dropped; free those with [`IdTree::drop_iteratively`].
+#[derive(Debug, Default)]
 pub enum IdTree {
     Zero,
     #[default]
@@ -23,94 +44,375 @@ impl IdTree {
         Self::Zero
     }
 
+    /// Builds a subtree, normalizing it so that equal ids share a single representation.
     pub fn subtree(left: IdTree, right: IdTree) -> Self {
-        Self::SubTree(Box::new(left), Box::new(right))
+        Self::node(left.normalize(), right.normalize())
     }
 
     /// Consumes to create id_left and id_right
     pub fn fork(self) -> (Self, Self) {
         use IdTree::*;
-        match self {
-            Zero => (Zero, Zero),
-            One => (
-                SubTree(Box::new(One), Box::new(Zero)),
-                SubTree(Box::new(Zero), Box::new(One)),
-            ),
-            SubTree(a, b) if a.is_zero() => {
-                let (a, b) = b.fork();
-                (
-                    SubTree(Box::new(Zero), Box::new(a)),
-                    SubTree(Box::new(Zero), Box::new(b)),
-                )
+
+        // Walk down past subtrees with a single owned side, remembering which side it was on.
+        let mut path = vec![];
+        let mut id = self;
+        let (a, b) = loop {
+            match id {
+                Zero => break (Zero, Zero),
+                One => break (Self::node(One, Zero), Self::node(Zero, One)),
+                SubTree(..) => {
+                    let (l, r) = id.into_children();
+                    match (l.is_zero(), r.is_zero()) {
+                        (true, _) => {
+                            path.push(true);
+                            id = r;
+                        }
+                        (_, true) => {
+                            path.push(false);
+                            id = l;
+                        }
+                        _ => break (Self::node(l, Zero), Self::node(Zero, r)),
+                    }
+                }
             }
-            SubTree(a, b) if b.is_zero() => {
-                let (a, b) = a.fork();
-                (
-                    SubTree(Box::new(a), Box::new(Zero)),
-                    SubTree(Box::new(b), Box::new(Zero)),
-                )
+        };
+
+        (Self::wrap(a, &path), Self::wrap(b, &path))
+    }
+
+    /// Consumes to create `count` ids in left-to-right interval order, forking as evenly as
+    /// possible so their depths below `self` differ by at most one. No ids are returned for a
+    /// `count` of zero.
+    pub fn fork_many(self, count: usize) -> Vec<Self> {
+        use IdTree::*;
+
+        // Descends once, splitting `count` between the sides wherever `fork` would, and builds
+        // each id up from its part of `self` along the sides taken from the root.
+        let mut ids = Vec::with_capacity(count);
+        let mut path = vec![];
+        let mut stack = vec![(self, count, 0, None)];
+        while let Some((mut id, count, depth, side)) = stack.pop() {
+            path.truncate(depth);
+            path.extend(side);
+            if count < 2 {
+                ids.extend((count == 1).then(|| Self::wrap(id, &path)));
+                continue;
             }
-            SubTree(a, b) => (SubTree(a, Box::new(Zero)), SubTree(Box::new(Zero), b)),
+
+            // Walk down past subtrees with a single owned side, as `fork` does.
+            let (l, r) = loop {
+                match id {
+                    Zero => break (Zero, Zero),
+                    One => break (One, One),
+                    SubTree(..) => {
+                        let (l, r) = id.into_children();
+                        match (l.is_zero(), r.is_zero()) {
+                            (true, _) => {
+                                path.push(true);
+                                id = r;
+                            }
+                            (_, true) => {
+                                path.push(false);
+                                id = l;
+                            }
+                            _ => break (l, r),
+                        }
+                    }
+                }
+            };
+            stack.push((r, count / 2, path.len(), Some(true)));
+            stack.push((l, count - count / 2, path.len(), Some(false)));
         }
+        ids
     }
 
-    /// Consumes to create many Ids
-    pub fn fork_many(self, count: usize) -> Vec<Self> {
-        if count == 1 {
-            return vec![self];
+    /// Places `id` below the given sides, taken from the root down, with `0` beside it.
+    fn wrap(mut id: Self, path: &[bool]) -> Self {
+        for right in path.iter().rev() {
+            id = if *right {
+                IdTree::SubTree(Box::new(IdTree::Zero), Box::new(id))
+            } else {
+                IdTree::SubTree(Box::new(id), Box::new(IdTree::Zero))
+            };
         }
+        id
+    }
 
-        // TODO: There is certainly a more efficient method than this, but this definitely works and
-        // keeps it somewhat balanced.
-        let mut ids = VecDeque::new();
-        let (l, r) = self.fork();
-        ids.push_back(l);
-        ids.push_back(r);
+    /// Consumes to create one id per weight in left-to-right interval order, each owning
+    /// approximately its weight's share of `self`. Weights which are negative or NaN count as
+    /// zero, though like [`IdTree::fork_weighted`] every id still gets some part of `self`.
+    pub fn fork_many_weighted(self, weights: &[f64]) -> Vec<Self> {
+        let weights: Vec<_> = weights
+            .iter()
+            .map(|w| if *w > 0.0 { *w } else { 0.0 })
+            .collect();
 
-        while ids.len() < count {
-            let f = ids
-                .pop_front()
-                .expect("Bug in this algorithm; VecDeque should never be empty");
-            let (l, r) = f.fork();
-            ids.push_back(l);
-            ids.push_back(r);
-        }
+        let mut ids = Vec::with_capacity(weights.len());
+        let mut stack = vec![(self, &weights[..])];
+        while let Some((id, weights)) = stack.pop() {
+            match weights {
+                [] => {}
+                [_] => ids.push(id),
+                _ => {
+                    // Split where the total weight is halved most closely, keeping both sides
+                    let total: f64 = weights.iter().sum();
+                    let mut split = 1;
+                    let mut seen = weights[0];
+                    while split + 1 < weights.len() && seen + weights[split] / 2.0 < total / 2.0 {
+                        seen += weights[split];
+                        split += 1;
+                    }
+                    let ratio = if total > 0.0 {
+                        seen / total
+                    } else {
+                        split as f64 / weights.len() as f64
+                    };
 
-        ids.into()
+                    let (l, r) = id.fork_weighted(ratio);
+                    let (lw, rw) = weights.split_at(split);
+                    stack.push((r, rw));
+                    stack.push((l, lw));
+                }
+            }
+        }
+        ids
     }
 
     /// Consumes to merge two ids
     pub fn join(self, other: Self) -> Self {
         use IdTree::*;
-        match (self, other) {
-            (Zero, b) => b,
-            (a, Zero) => a,
-            (One, _) | (_, One) => One,
-            (SubTree(l0, r0), SubTree(l1, r1)) => {
-                let l = l0.join(*l1).norm();
-                let r = r0.join(*r1).norm();
-                SubTree(Box::new(l), Box::new(r)).norm()
+
+        enum Frame {
+            Visit(IdTree, IdTree),
+            Node,
+        }
+
+        let mut frames = vec![Frame::Visit(self, other)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(a, b) => match (&a, &b) {
+                    (Zero, _) => done.push(b.normalize()),
+                    (_, Zero) => done.push(a.normalize()),
+                    (One, _) | (_, One) => {
+                        a.drop_iteratively();
+                        b.drop_iteratively();
+                        done.push(One);
+                    }
+                    (SubTree(..), SubTree(..)) => {
+                        let (l0, r0) = a.into_children();
+                        let (l1, r1) = b.into_children();
+                        frames.push(Frame::Node);
+                        frames.push(Frame::Visit(r0, r1));
+                        frames.push(Frame::Visit(l0, l1));
+                    }
+                },
+                Frame::Node => Self::push_node(&mut done),
             }
         }
+        done.pop().expect("The root is always built")
+    }
+
+    /// Merges two ids which must be disjoint, as ids forked from a common one always are. An
+    /// overlap means two replicas have been recording events as the same id.
+    pub fn try_join(self, other: Self) -> Result<Self, OverlapError> {
+        self.check_disjoint(&other)?;
+        Ok(self.join(other))
     }
 
-    fn norm(&self) -> Self {
+    /// The part of the interval owned by both ids.
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a && b)
+    }
+
+    /// The part of the interval owned by `self` but not by `other`.
+    pub fn difference(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a && !b)
+    }
+
+    /// Whether every part of the interval owned by `self` is also owned by `other`.
+    pub fn is_subset_of(&self, other: &Self) -> bool {
+        !self.any_pair(other, |a, b| a && !b)
+    }
+
+    /// Whether some part of the interval is owned by both ids.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.any_pair(other, |a, b| a && b)
+    }
+
+    /// Whether no part of the interval is owned by both ids, as for any two live replicas.
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        !self.overlaps(other)
+    }
+
+    /// Fails with the overlap unless the ids are disjoint.
+    fn check_disjoint(&self, other: &Self) -> Result<(), OverlapError> {
+        if self.overlaps(other) {
+            return Err(OverlapError {
+                overlap: self.intersection(other),
+            });
+        }
+        Ok(())
+    }
+
+    /// Builds the id owning each point where `op` holds for whether `self` and `other` own it.
+    fn combine(&self, other: &Self, op: fn(bool, bool) -> bool) -> Self {
         use IdTree::*;
+
+        let mut frames = vec![Some((self, other))];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some((a @ (Zero | One), b @ (Zero | One))) => {
+                    done.push(if op(a.is_one(), b.is_one()) {
+                        One
+                    } else {
+                        Zero
+                    })
+                }
+                Some((a, b)) => {
+                    let ((l0, r0), (l1, r1)) = (a.children(), b.children());
+                    frames.push(None);
+                    frames.push(Some((r0, r1)));
+                    frames.push(Some((l0, l1)));
+                }
+                None => Self::push_node(&mut done),
+            }
+        }
+        done.pop().expect("The root is always built")
+    }
+
+    /// Whether `pred` holds at any point for whether `self` and `other` own it.
+    fn any_pair(&self, other: &Self, pred: fn(bool, bool) -> bool) -> bool {
+        use IdTree::*;
+
+        let mut stack = vec![(self, other)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (a @ (Zero | One), b @ (Zero | One)) => {
+                    if pred(a.is_one(), b.is_one()) {
+                        return true;
+                    }
+                }
+                (a, b) => {
+                    let ((l0, r0), (l1, r1)) = (a.children(), b.children());
+                    stack.push((r0, r1));
+                    stack.push((l0, l1));
+                }
+            }
+        }
+        false
+    }
+
+    /// The children of a subtree; a leaf stands in for both of its own children.
+    fn children(&self) -> (&Self, &Self) {
         match self {
-            SubTree(l, r) => {
-                let l = l.norm();
-                let r = r.norm();
+            IdTree::SubTree(l, r) => (l, r),
+            leaf => (leaf, leaf),
+        }
+    }
+
+    fn is_one(&self) -> bool {
+        matches!(self, IdTree::One)
+    }
+
+    /// Brings the id into its canonical form, collapsing `(0, 0)` and `(1, 1)`.
+    pub fn normalize(self) -> Self {
+        if self.is_normalized() {
+            return self;
+        }
+
+        let mut frames = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(id @ IdTree::SubTree(..)) => {
+                    let (l, r) = id.into_children();
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                Some(id) => done.push(id),
+                None => Self::push_node(&mut done),
+            }
+        }
+        done.pop().expect("The root is always built")
+    }
+
+    pub fn is_normalized(&self) -> bool {
+        use IdTree::*;
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            if let SubTree(l, r) = id {
+                if matches!((l.as_ref(), r.as_ref()), (Zero, Zero) | (One, One)) {
+                    return false;
+                }
+                stack.push(r);
+                stack.push(l);
+            }
+        }
+        true
+    }
+
+    /// Builds a node over already normalized children.
+    fn node(l: Self, r: Self) -> Self {
+        use IdTree::*;
+        match (&l, &r) {
+            (Zero, Zero) => Zero,
+            (One, One) => One,
+            _ => SubTree(Box::new(l), Box::new(r)),
+        }
+    }
+
+    /// Replaces the top two ids on `done` with the node built over them.
+    fn push_node(done: &mut Vec<Self>) {
+        let r = done.pop().expect("Right child is built first");
+        let l = done.pop().expect("Left child is built first");
+        done.push(Self::node(l, r));
+    }
 
-                match (&l, &r) {
-                    (&Zero, &Zero) => return Zero,
-                    (&One, &One) => return One,
-                    _ => {}
+    /// Moves the children out of a subtree; any other id has no children and yields zeros.
+    fn into_children(mut self) -> (Self, Self) {
+        match &mut self {
+            IdTree::SubTree(l, r) => (
+                std::mem::replace(l, IdTree::Zero),
+                std::mem::replace(r, IdTree::Zero),
+            ),
+            _ => (IdTree::Zero, IdTree::Zero),
+        }
+    }
+
+    fn hash_normalized<H: std::hash::Hasher>(&self, state: &mut H) {
+        use IdTree::*;
+        use std::hash::Hash;
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            match id {
+                Zero => 0u8.hash(state),
+                One => 1u8.hash(state),
+                SubTree(l, r) => {
+                    2u8.hash(state);
+                    stack.push(r);
+                    stack.push(l);
                 }
+            }
+        }
+    }
 
-                SubTree(Box::new(l), Box::new(r))
+    /// Whether the id owns no part of the interval, i.e. is equivalent to `0`.
+    pub fn is_anonymous(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            match id {
+                IdTree::Zero => {}
+                IdTree::One => return false,
+                IdTree::SubTree(l, r) => {
+                    stack.push(r);
+                    stack.push(l);
+                }
             }
-            _ => self.clone(),
         }
+        true
     }
 
     fn is_zero(&self) -> bool {
@@ -118,14 +420,144 @@ impl IdTree {
     }
 }
 
+/// Ids are equal when they own the same part of the interval, regardless of their shape.
+impl PartialEq for IdTree {
+    fn eq(&self, other: &Self) -> bool {
+        use IdTree::*;
+        let mut stack = vec![(self, other)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (Zero, Zero) | (One, One) => {}
+                (Zero, One) | (One, Zero) => return false,
+                (a @ (Zero | One), SubTree(l, r)) | (SubTree(l, r), a @ (Zero | One)) => {
+                    stack.push((a, r));
+                    stack.push((a, l));
+                }
+                (SubTree(l0, r0), SubTree(l1, r1)) => {
+                    stack.push((r0, r1));
+                    stack.push((l0, l1));
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Eq for IdTree {}
+
+impl PartialOrd for IdTree {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ids are compared point by point from the left of the interval, with an owned point sorting
+/// before an unowned one, so that disjoint ids are ordered by the intervals they own.
+impl Ord for IdTree {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use IdTree::*;
+        let mut stack = vec![(self, other)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (a @ (Zero | One), b @ (Zero | One)) => match b.is_one().cmp(&a.is_one()) {
+                    std::cmp::Ordering::Equal => {}
+                    ordering => return ordering,
+                },
+                (a, b) => {
+                    let ((l0, r0), (l1, r1)) = (a.children(), b.children());
+                    stack.push((r0, r1));
+                    stack.push((l0, l1));
+                }
+            }
+        }
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for IdTree {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        if self.is_normalized() {
+            self.hash_normalized(state);
+        } else {
+            let normalized = self.clone().normalize();
+            normalized.hash_normalized(state);
+            normalized.drop_iteratively();
+        }
+    }
+}
+
+impl Clone for IdTree {
+    fn clone(&self) -> Self {
+        use IdTree::*;
+        let mut frames = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(Zero) => done.push(Zero),
+                Some(One) => done.push(One),
+                Some(SubTree(l, r)) => {
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let r = done.pop().expect("Right child is cloned first");
+                    let l = done.pop().expect("Left child is cloned first");
+                    done.push(SubTree(Box::new(l), Box::new(r)));
+                }
+            }
+        }
+        done.pop().expect("The root is always cloned")
+    }
+}
+
+impl IdTree {
+    /// Drops the id one node at a time. Dropping an id as usual recurses once per level, which
+    /// can overflow the stack for ids many thousands of levels deep.
+    pub fn drop_iteratively(mut self) {
+        let mut stack = vec![];
+        self.detach_subtrees(&mut stack);
+        while let Some(mut id) = stack.pop() {
+            id.detach_subtrees(&mut stack);
+        }
+    }
+
+    fn detach_subtrees(&mut self, stack: &mut Vec<Self>) {
+        if let IdTree::SubTree(l, r) = self {
+            for child in [l, r] {
+                if matches!(**child, IdTree::SubTree(..)) {
+                    stack.push(std::mem::replace(child, IdTree::Zero));
+                }
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for IdTree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         use IdTree::*;
-        match self {
-            Zero => write!(f, "0"),
-            One => write!(f, "1"),
-            SubTree(l, r) => write!(f, "({}, {})", l, r),
+
+        enum Token<'a> {
+            Id(&'a IdTree),
+            Text(&'static str),
+        }
+
+        let mut stack = vec![Token::Id(self)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Id(Zero) => write!(f, "0")?,
+                Token::Id(One) => write!(f, "1")?,
+                Token::Id(SubTree(l, r)) => {
+                    write!(f, "(")?;
+                    stack.push(Token::Text(")"));
+                    stack.push(Token::Id(r));
+                    stack.push(Token::Text(", "));
+                    stack.push(Token::Id(l));
+                }
+            }
         }
+        Ok(())
     }
 }
 
@@ -175,11 +607,11 @@ mod tests {
 
         let ids = i.fork_many(5);
 
-        assert_eq!(&ids[0].to_string(), "((0, 1), 0)");
-        assert_eq!(&ids[1].to_string(), "(0, (1, 0))");
-        assert_eq!(&ids[2].to_string(), "(0, (0, 1))");
-        assert_eq!(&ids[3].to_string(), "(((1, 0), 0), 0)");
-        assert_eq!(&ids[4].to_string(), "(((0, 1), 0), 0)");
+        assert_eq!(&ids[0].to_string(), "(((1, 0), 0), 0)");
+        assert_eq!(&ids[1].to_string(), "(((0, 1), 0), 0)");
+        assert_eq!(&ids[2].to_string(), "((0, 1), 0)");
+        assert_eq!(&ids[3].to_string(), "(0, (1, 0))");
+        assert_eq!(&ids[4].to_string(), "(0, (0, 1))");
     }
 
     #[test]
@@ -189,8 +621,218 @@ mod tests {
         let ids = i.clone().fork_many(1);
         assert_eq!(&ids[0].to_string(), "1");
 
-        let ids = i.fork_many(2);
+        let ids = i.clone().fork_many(2);
         assert_eq!(&ids[0].to_string(), "(1, 0)");
         assert_eq!(&ids[1].to_string(), "(0, 1)");
+
+        assert!(i.fork_many(0).is_empty());
+    }
+
+    #[test]
+    fn test_fork_multi_balanced() {
+        for count in 1..=40 {
+            let ids = IdTree::one().fork_many(count);
+            assert_eq!(ids.len(), count);
+
+            // Depths are minimal and differ by at most one
+            let depths: Vec<_> = ids.iter().map(|id| id.measure().exponent()).collect();
+            let max = count.next_power_of_two().trailing_zeros() as usize;
+            assert!(depths.iter().all(|d| *d == max || *d + 1 == max));
+
+            // Ids are in interval order and together own everything
+            let starts: Vec<_> = ids.iter().flat_map(|id| id.intervals()).collect();
+            assert!(starts.windows(2).all(|w| w[0].end == w[1].start));
+            let all = ids.into_iter().fold(IdTree::zero(), IdTree::join);
+            assert_eq!(all, IdTree::one());
+        }
+    }
+
+    #[test]
+    fn test_fork_multi_matches_fork() {
+        // Splitting `count` in half at each fork, as `fork_many` did before descending only once
+        fn forked(id: IdTree, count: usize) -> Vec<IdTree> {
+            match count {
+                0 => vec![],
+                1 => vec![id],
+                _ => {
+                    let (l, r) = id.fork();
+                    let mut ids = forked(l, count - count / 2);
+                    ids.extend(forked(r, count / 2));
+                    ids
+                }
+            }
+        }
+
+        use IdTree::*;
+        let fragmented = IdTree::subtree(
+            Zero,
+            IdTree::subtree(IdTree::subtree(One, IdTree::subtree(Zero, One)), Zero),
+        );
+        for id in [IdTree::one(), IdTree::one().fork().1, fragmented] {
+            for count in 0..=20 {
+                let ids: Vec<_> = id
+                    .clone()
+                    .fork_many(count)
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect();
+                let expected: Vec<_> = forked(id.clone(), count)
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect();
+                assert_eq!(ids, expected, "Forking {id} into {count}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fork_multi_weighted() {
+        let ids = IdTree::one().fork_many_weighted(&[1.0, 1.0, 2.0]);
+        let shares: Vec<_> = ids.iter().map(|id| id.measure().to_string()).collect();
+        assert_eq!(shares, ["1/4", "1/4", "1/2"]);
+        assert_eq!(ids[0].to_string(), "((1, 0), 0)");
+
+        let ids = IdTree::one().fork_many_weighted(&[3.0, 0.0, f64::NAN, 1.0]);
+        assert_eq!(ids.len(), 4);
+        assert!(ids.iter().all(|id| !id.is_anonymous()));
+        assert!((ids[0].measure().to_f64() - 0.75).abs() < 0.01);
+        assert_eq!(
+            ids.into_iter().fold(IdTree::zero(), IdTree::join),
+            IdTree::one()
+        );
+
+        assert!(IdTree::one().fork_many_weighted(&[]).is_empty());
+        assert_eq!(
+            IdTree::one().fork_many_weighted(&[0.0, 0.0]),
+            IdTree::one().fork_many(2)
+        );
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let ab = a.clone().join(b.clone());
+        let bc = b.clone().join(c.clone());
+
+        assert_eq!(ab.intersection(&bc), b);
+        assert_eq!(ab.difference(&bc), a);
+        assert_eq!(bc.difference(&ab), c);
+        assert_eq!(a.intersection(&c), IdTree::zero());
+        assert_eq!(IdTree::one().difference(&a), bc);
+        assert_eq!(IdTree::one().intersection(&bc), bc);
+
+        assert!(b.is_subset_of(&ab));
+        assert!(!ab.is_subset_of(&b));
+        assert!(IdTree::zero().is_subset_of(&a));
+        assert!(ab.is_subset_of(&IdTree::one()));
+
+        assert!(ab.overlaps(&bc));
+        assert!(a.is_disjoint(&bc));
+        assert!(!IdTree::zero().overlaps(&IdTree::one()));
+    }
+
+    #[test]
+    fn test_try_join() {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let ab = a.clone().join(b.clone());
+
+        assert_eq!(a.clone().try_join(c.clone()), Ok(a.clone().join(c)));
+        assert_eq!(ab.try_join(b.clone()), Err(OverlapError { overlap: b }));
+        assert_eq!(
+            IdTree::one().try_join(IdTree::one()),
+            Err(OverlapError {
+                overlap: IdTree::one()
+            })
+        );
+        assert_eq!(a.clone().try_join(IdTree::zero()), Ok(a));
+    }
+
+    #[test]
+    fn test_ord() {
+        use IdTree::*;
+        use std::collections::BTreeSet;
+
+        let ids = IdTree::one().fork_many(5);
+        let mut sorted = ids.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, ids);
+
+        let unnormalized = SubTree(
+            Box::new(SubTree(Box::new(One), Box::new(One))),
+            Box::new(Zero),
+        );
+        assert_eq!(
+            unnormalized.cmp(&IdTree::subtree(One, Zero)),
+            std::cmp::Ordering::Equal
+        );
+        assert!(BTreeSet::from([IdTree::subtree(One, Zero)]).contains(&unnormalized));
+        assert!(One < unnormalized && unnormalized < Zero);
+    }
+
+    #[test]
+    fn test_normalize() {
+        use IdTree::*;
+
+        let id = SubTree(
+            Box::new(SubTree(Box::new(One), Box::new(One))),
+            Box::new(SubTree(Box::new(Zero), Box::new(Zero))),
+        );
+        assert!(!id.is_normalized());
+        assert_eq!(id.clone().normalize().to_string(), "(1, 0)");
+        assert_eq!(id, IdTree::subtree(One, Zero));
+
+        assert_eq!(IdTree::subtree(One, One), One);
+        assert_eq!(IdTree::subtree(Zero, IdTree::subtree(Zero, Zero)), Zero);
+        assert!(IdTree::subtree(Zero, One).is_normalized());
+    }
+
+    #[test]
+    fn test_hash_normalized() {
+        use IdTree::*;
+        use std::collections::HashMap;
+
+        let mut map = HashMap::new();
+        map.insert(IdTree::subtree(One, Zero), "left");
+        let unnormalized = SubTree(
+            Box::new(SubTree(Box::new(One), Box::new(One))),
+            Box::new(Zero),
+        );
+        assert_eq!(map.get(&unnormalized), Some(&"left"));
+    }
+
+    #[test]
+    fn test_deep_trees() {
+        use IdTree::*;
+        use std::collections::HashSet;
+
+        const DEPTH: usize = 50_000;
+
+        let mut id = One;
+        let mut unnormalized = One;
+        for _ in 0..DEPTH {
+            id = SubTree(Box::new(Zero), Box::new(id));
+            unnormalized = SubTree(Box::new(unnormalized), Box::new(One));
+        }
+
+        let copy = id.clone();
+        assert_eq!(copy, id);
+        assert!(id.is_normalized());
+        let set = HashSet::from([copy.clone()]);
+        assert!(set.contains(&id));
+        assert_eq!(id.to_string().len(), "(0, )".len() * DEPTH + 1);
+
+        let (left, right) = copy.fork();
+        assert_ne!(left, right);
+        let joined = left.join(right);
+        assert_eq!(joined, id);
+        assert_eq!(unnormalized.normalize(), One);
+
+        // Dropping as usual would recurse once per level
+        for id in set.into_iter().chain([id, joined]) {
+            id.drop_iteratively();
+        }
     }
 }
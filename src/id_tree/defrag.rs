@@ -0,0 +1,148 @@
+use super::*;
+
+impl IdTree {
+    /// The number of separate leaves owned by the id, each one an interval which an
+    /// [`EventTree`](crate::EventTree) growing under the id may need to track.
+    pub fn leaf_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            match id {
+                IdTree::Zero => {}
+                IdTree::One => count += 1,
+                IdTree::SubTree(l, r) => {
+                    stack.push(r);
+                    stack.push(l);
+                }
+            }
+        }
+        count
+    }
+
+    /// The length of the longest path from the root down to a leaf.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(self, 0)];
+        while let Some((id, d)) = stack.pop() {
+            depth = depth.max(d);
+            if let IdTree::SubTree(l, r) = id {
+                stack.push((r, d + 1));
+                stack.push((l, d + 1));
+            }
+        }
+        depth
+    }
+
+    /// The number of nodes in the tree, counting leaves.
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            size += 1;
+            if let IdTree::SubTree(l, r) = id {
+                stack.push(r);
+                stack.push(l);
+            }
+        }
+        size
+    }
+
+    /// The index of the peer whose id joins with this one into the smallest tree, preferring
+    /// the shallower result and then the earlier peer on ties. Peers overlapping this id are
+    /// skipped, since joining them would corrupt both clocks.
+    pub fn best_join_partner(&self, peers: &[IdTree]) -> Option<usize> {
+        peers
+            .iter()
+            .enumerate()
+            .filter(|(_, peer)| self.is_disjoint(peer))
+            .map(|(i, peer)| {
+                let joined = self.clone().join(peer.clone());
+                ((joined.size(), joined.depth()), i)
+            })
+            .min()
+            .map(|(_, i)| i)
+    }
+
+    /// Swaps the ids of two replicas for a compact pair owning the same part of the interval,
+    /// forked afresh from their union. The original ids are returned when that would not shrink
+    /// them.
+    ///
+    /// Both replicas must have synced their timestamps before adopting the new ids, as each may
+    /// now own part of the interval where only the other recorded events; see
+    /// [`ItcPair::refork`](crate::ItcPair::refork).
+    pub fn refork(self, other: Self) -> Result<(Self, Self), OverlapError> {
+        self.check_disjoint(&other)?;
+
+        let (a, b) = self.clone().join(other.clone()).fork();
+        let cost = |a: &Self, b: &Self| (a.leaf_count() + b.leaf_count(), a.size() + b.size());
+        if cost(&a, &b) < cost(&self, &other) {
+            Ok((a, b))
+        } else {
+            Ok((self, other))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ids `0, 2, 4, 6` and `1, 3, 5, 7` of the interval split in eight.
+    fn interleaved() -> (IdTree, IdTree) {
+        let mut even = IdTree::zero();
+        let mut odd = IdTree::zero();
+        for (i, id) in IdTree::one().fork_many(8).into_iter().enumerate() {
+            if i % 2 == 0 {
+                even = even.join(id);
+            } else {
+                odd = odd.join(id);
+            }
+        }
+        (even, odd)
+    }
+
+    #[test]
+    fn test_metrics() {
+        let (even, _) = interleaved();
+        assert_eq!(even.leaf_count(), 4);
+        assert_eq!(even.depth(), 3);
+        assert_eq!(even.size(), 15);
+
+        assert_eq!(IdTree::one().leaf_count(), 1);
+        assert_eq!(IdTree::one().depth(), 0);
+        assert_eq!(IdTree::zero().leaf_count(), 0);
+    }
+
+    #[test]
+    fn test_best_join_partner() {
+        let ids = IdTree::one().fork_many(8);
+        let peers = [
+            ids[5].clone(),
+            ids[1].clone(),
+            ids[0].clone(),
+            ids[2].clone(),
+        ];
+
+        // The sibling collapses back into its parent, the overlapping id itself is skipped
+        assert_eq!(ids[0].best_join_partner(&peers), Some(1));
+        assert_eq!(ids[0].best_join_partner(&peers[2..]), Some(1));
+        assert_eq!(ids[0].best_join_partner(&[ids[0].clone()]), None);
+        assert_eq!(ids[0].best_join_partner(&[]), None);
+    }
+
+    #[test]
+    fn test_refork() {
+        let (even, odd) = interleaved();
+        let (a, b) = even.clone().refork(odd.clone()).expect("Disjoint ids");
+        assert_eq!(a.to_string(), "(1, 0)");
+        assert_eq!(b.to_string(), "(0, 1)");
+
+        // Already compact ids are kept as they are
+        let (l, r) = IdTree::one().fork();
+        let (_, rr) = r.fork();
+        assert_eq!(l.clone().refork(rr.clone()), Ok((l.clone(), rr.clone())));
+
+        let overlap = even.clone().refork(even.clone().join(odd));
+        assert_eq!(overlap, Err(OverlapError { overlap: even }));
+    }
+}
@@ -0,0 +1,165 @@
+use super::*;
+use crate::encoding::{BitReader, BitWriter, Children, DecodeError, DecodeOptions};
+
+impl IdTree {
+    /// Encodes the id using the compact bit encoding from the original paper.
+    ///
+    /// Ids nested deeper than [`MAX_DECODE_DEPTH`](crate::MAX_DECODE_DEPTH) have to be decoded
+    /// with a larger [`DecodeOptions::max_depth`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        self.encode_into(&mut writer);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_with(bytes, &DecodeOptions::default())
+    }
+
+    pub fn decode_with(bytes: &[u8], options: &DecodeOptions) -> Result<Self, DecodeError> {
+        let mut reader = BitReader::with_options(bytes, options);
+        let id = Self::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(id)
+    }
+
+    pub(crate) fn encode_into(&self, writer: &mut BitWriter) {
+        use IdTree::*;
+        let mut stack = vec![self];
+        while let Some(id) = stack.pop() {
+            match id {
+                Zero => writer.write_bits(0b000, 3),
+                One => writer.write_bits(0b001, 3),
+                SubTree(l, r) if l.is_zero() => {
+                    writer.write_bits(1, 2);
+                    stack.push(r);
+                }
+                SubTree(l, r) if r.is_zero() => {
+                    writer.write_bits(2, 2);
+                    stack.push(l);
+                }
+                SubTree(l, r) => {
+                    writer.write_bits(3, 2);
+                    stack.push(r);
+                    stack.push(l);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn decode_from(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        use IdTree::*;
+
+        // Nodes whose children are still being decoded, along with the left child once it is
+        let mut frames: Vec<(Children, Option<Self>)> = vec![];
+        loop {
+            let tag = reader.read_bits(2)?;
+            let mut id = if tag == 0 {
+                if reader.read_bit()? { One } else { Zero }
+            } else {
+                reader.check_depth(frames.len())?;
+                let children = match tag {
+                    1 => Children::Right,
+                    2 => Children::Left,
+                    _ => Children::Both,
+                };
+                frames.push((children, None));
+                continue;
+            };
+
+            // Builds every node whose last child has just been decoded
+            loop {
+                id = match frames.pop() {
+                    None => return Ok(id),
+                    Some((Children::Right, _)) => Self::node(Zero, id),
+                    Some((Children::Left, _)) => Self::node(id, Zero),
+                    Some((Children::Both, Some(l))) => Self::node(l, id),
+                    Some((Children::Both, None)) => {
+                        frames.push((Children::Both, Some(id)));
+                        break;
+                    }
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_golden() {
+        use IdTree::*;
+
+        let vectors = [
+            (Zero, vec![0x00]),
+            (One, vec![0x20]),
+            (IdTree::subtree(One, Zero), vec![0x88]),
+            (IdTree::subtree(Zero, One), vec![0x48]),
+            (
+                IdTree::subtree(IdTree::subtree(One, Zero), IdTree::subtree(Zero, One)),
+                vec![0xe2, 0x90],
+            ),
+        ];
+
+        for (id, bytes) in vectors {
+            assert_eq!(id.encode(), bytes, "Encoding {id}");
+            assert_eq!(IdTree::decode(&bytes), Ok(id));
+        }
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let ids = IdTree::one().fork_many(13);
+        for id in ids {
+            assert_eq!(IdTree::decode(&id.encode()), Ok(id));
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(IdTree::decode(&[]), Err(DecodeError::UnexpectedEnd(0)));
+        assert_eq!(IdTree::decode(&[0xe0]), Err(DecodeError::UnexpectedEnd(8)));
+        assert_eq!(
+            IdTree::decode(&[0x20, 0x00]),
+            Err(DecodeError::TrailingData(3))
+        );
+        assert_eq!(IdTree::decode(&[0x21]), Err(DecodeError::TrailingData(3)));
+        assert_eq!(
+            IdTree::decode(&[0x55; 200]),
+            Err(DecodeError::TooDeep(1026))
+        );
+    }
+
+    #[test]
+    fn test_decode_deep() {
+        // Far deeper than decoding could recurse through
+        const DEPTH: usize = 100_000;
+        let mut id = IdTree::One;
+        for i in 0..DEPTH {
+            id = if i % 2 == 1 {
+                IdTree::SubTree(Box::new(id), Box::new(IdTree::One))
+            } else {
+                IdTree::SubTree(Box::new(IdTree::Zero), Box::new(id))
+            };
+        }
+
+        let bytes = id.encode();
+        assert!(matches!(
+            IdTree::decode(&bytes),
+            Err(DecodeError::TooDeep(_))
+        ));
+        let options = DecodeOptions::new().max_depth(DEPTH - 1);
+        assert!(matches!(
+            IdTree::decode_with(&bytes, &options),
+            Err(DecodeError::TooDeep(_))
+        ));
+
+        let options = DecodeOptions::new().max_depth(DEPTH);
+        let decoded = IdTree::decode_with(&bytes, &options).expect("Deep enough");
+        assert_eq!(decoded.encode(), bytes);
+        decoded.drop_iteratively();
+        id.drop_iteratively();
+    }
+}
@@ -0,0 +1,307 @@
+use super::*;
+use crate::flat::subtree_ends;
+
+/// An [`IdTree`] laid out in preorder within a single `Vec`, avoiding an allocation per node.
+///
+/// Always kept normalized, so ids are equal exactly when their nodes are.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlatIdTree {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Node {
+    Zero,
+    One,
+    /// Followed by the left and then the right subtree.
+    SubTree,
+}
+
+impl FlatIdTree {
+    pub fn one() -> Self {
+        Self {
+            nodes: vec![Node::One],
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self {
+            nodes: vec![Node::Zero],
+        }
+    }
+
+    /// Consumes to create id_left and id_right
+    pub fn fork(self) -> (Self, Self) {
+        use Node::*;
+
+        if self.is_anonymous() {
+            return (Self::zero(), Self::zero());
+        }
+
+        // Walk down past subtrees with a single owned side, copying the path into both halves.
+        // Left-owned subtrees still need their zero right child once the rest is written.
+        let ends = self.ends();
+        let mut a = vec![];
+        let mut b = vec![];
+        let mut zeros = 0;
+        let mut i = 0;
+        loop {
+            if self.nodes[i] == One {
+                a.extend([SubTree, One, Zero]);
+                b.extend([SubTree, Zero, One]);
+                break;
+            }
+
+            let (l, r) = (i + 1, ends[i + 1]);
+            match (self.nodes[l], self.nodes[r]) {
+                (Zero, _) => {
+                    a.extend([SubTree, Zero]);
+                    b.extend([SubTree, Zero]);
+                    i = r;
+                }
+                (_, Zero) => {
+                    a.push(SubTree);
+                    b.push(SubTree);
+                    zeros += 1;
+                    i = l;
+                }
+                _ => {
+                    a.push(SubTree);
+                    a.extend_from_slice(&self.nodes[l..r]);
+                    a.push(Zero);
+                    b.extend([SubTree, Zero]);
+                    b.extend_from_slice(&self.nodes[r..ends[r]]);
+                    break;
+                }
+            }
+        }
+
+        a.extend(std::iter::repeat_n(Zero, zeros));
+        b.extend(std::iter::repeat_n(Zero, zeros));
+        (Self { nodes: a }, Self { nodes: b })
+    }
+
+    /// Consumes to merge two ids
+    pub fn join(self, other: Self) -> Self {
+        use Node::*;
+
+        enum Frame {
+            Visit(usize, usize),
+            Close(usize),
+        }
+
+        let (a_ends, b_ends) = (self.ends(), other.ends());
+        let mut nodes = vec![];
+        let mut starts = vec![];
+        let mut frames = vec![Frame::Visit(0, 0)];
+        while let Some(frame) = frames.pop() {
+            let (i, j) = match frame {
+                Frame::Visit(i, j) => (i, j),
+                Frame::Close(p) => {
+                    let r = starts.pop().expect("Right child is joined last");
+                    starts.pop().expect("Left child is joined first");
+                    Self::close(&mut nodes, p, r);
+                    starts.push(p);
+                    continue;
+                }
+            };
+
+            if let (SubTree, SubTree) = (self.nodes[i], other.nodes[j]) {
+                frames.push(Frame::Close(nodes.len()));
+                nodes.push(SubTree);
+                frames.push(Frame::Visit(a_ends[i + 1], b_ends[j + 1]));
+                frames.push(Frame::Visit(i + 1, j + 1));
+                continue;
+            }
+
+            starts.push(nodes.len());
+            match (self.nodes[i], other.nodes[j]) {
+                (Zero, _) => nodes.extend_from_slice(&other.nodes[j..b_ends[j]]),
+                (_, Zero) => nodes.extend_from_slice(&self.nodes[i..a_ends[i]]),
+                _ => nodes.push(One),
+            }
+        }
+        Self { nodes }
+    }
+
+    /// Whether the id owns no part of the interval, i.e. is equivalent to `0`.
+    pub fn is_anonymous(&self) -> bool {
+        self.nodes == [Node::Zero]
+    }
+
+    /// Normalizes the subtree node at `p` whose right child starts at `r`, assuming both children
+    /// are already normalized.
+    fn close(nodes: &mut Vec<Node>, p: usize, r: usize) {
+        match (nodes[p + 1], nodes[r]) {
+            (Node::Zero, Node::Zero) | (Node::One, Node::One) => {
+                let leaf = nodes[r];
+                nodes.truncate(p);
+                nodes.push(leaf);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub(crate) fn ends(&self) -> Vec<usize> {
+        subtree_ends(self.nodes.len(), |i| self.nodes[i] != Node::SubTree)
+    }
+}
+
+impl Default for FlatIdTree {
+    fn default() -> Self {
+        Self::one()
+    }
+}
+
+impl From<&IdTree> for FlatIdTree {
+    fn from(id: &IdTree) -> Self {
+        let mut nodes = vec![];
+        let mut starts = vec![];
+        let mut frames = vec![Some(id)];
+        let mut parents = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(IdTree::Zero) => {
+                    starts.push(nodes.len());
+                    nodes.push(Node::Zero);
+                }
+                Some(IdTree::One) => {
+                    starts.push(nodes.len());
+                    nodes.push(Node::One);
+                }
+                Some(IdTree::SubTree(l, r)) => {
+                    parents.push(nodes.len());
+                    nodes.push(Node::SubTree);
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let p = parents.pop().expect("Every close has a parent");
+                    let r = starts.pop().expect("Right child is flattened last");
+                    starts.pop().expect("Left child is flattened first");
+                    Self::close(&mut nodes, p, r);
+                    starts.push(p);
+                }
+            }
+        }
+        Self { nodes }
+    }
+}
+
+impl From<IdTree> for FlatIdTree {
+    fn from(id: IdTree) -> Self {
+        let flat = Self::from(&id);
+        id.drop_iteratively();
+        flat
+    }
+}
+
+impl From<&FlatIdTree> for IdTree {
+    fn from(id: &FlatIdTree) -> Self {
+        // Walking backwards completes the right and then the left child before their parent
+        let mut done = vec![];
+        for node in id.nodes.iter().rev() {
+            let id = match node {
+                Node::Zero => IdTree::Zero,
+                Node::One => IdTree::One,
+                Node::SubTree => {
+                    let l = done.pop().expect("Left child precedes its parent");
+                    let r = done.pop().expect("Right child precedes its parent");
+                    IdTree::SubTree(Box::new(l), Box::new(r))
+                }
+            };
+            done.push(id);
+        }
+        done.pop().expect("The root is always built")
+    }
+}
+
+impl From<FlatIdTree> for IdTree {
+    fn from(id: FlatIdTree) -> Self {
+        Self::from(&id)
+    }
+}
+
+impl std::fmt::Display for FlatIdTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let ends = self.ends();
+
+        enum Token {
+            Id(usize),
+            Text(&'static str),
+        }
+
+        let mut stack = vec![Token::Id(0)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Id(i) => match self.nodes[i] {
+                    Node::Zero => write!(f, "0")?,
+                    Node::One => write!(f, "1")?,
+                    Node::SubTree => {
+                        write!(f, "(")?;
+                        stack.push(Token::Text(")"));
+                        stack.push(Token::Id(ends[i + 1]));
+                        stack.push(Token::Text(", "));
+                        stack.push(Token::Id(i + 1));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> Vec<IdTree> {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+        let (a, d) = a.fork();
+        let (d, e) = d.fork();
+        vec![IdTree::zero(), IdTree::one(), a, b, c, d, e]
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for id in ids() {
+            let flat = FlatIdTree::from(&id);
+            assert_eq!(flat.to_string(), id.to_string());
+            assert_eq!(IdTree::from(flat), id);
+        }
+
+        let unnormalized = IdTree::SubTree(
+            Box::new(IdTree::SubTree(
+                Box::new(IdTree::One),
+                Box::new(IdTree::One),
+            )),
+            Box::new(IdTree::Zero),
+        );
+        assert_eq!(FlatIdTree::from(unnormalized).to_string(), "(1, 0)");
+    }
+
+    #[test]
+    fn test_fork_join() {
+        for id in ids() {
+            let (l, r) = id.clone().fork();
+            let (fl, fr) = FlatIdTree::from(&id).fork();
+            assert_eq!(fl.to_string(), l.to_string());
+            assert_eq!(fr.to_string(), r.to_string());
+            assert_eq!(IdTree::from(fl.join(fr)), id);
+        }
+
+        for a in ids() {
+            for b in ids() {
+                let flat = FlatIdTree::from(&a).join(FlatIdTree::from(&b));
+                assert_eq!(flat.to_string(), a.clone().join(b).to_string());
+            }
+        }
+    }
+}
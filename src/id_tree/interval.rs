@@ -0,0 +1,421 @@
+use super::*;
+use std::ops::Range;
+
+/// An exact dyadic rational `n / 2^k` within the unit interval, as used for the bounds and the
+/// measure of the parts of the interval owned by an [`IdTree`].
+///
+/// Exact at any depth, unlike a fixed-width fraction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dyadic {
+    /// Set only for exactly `1`.
+    whole: bool,
+    /// Binary digits after the point, most significant first, without trailing zeros.
+    bits: Vec<bool>,
+}
+
+impl Dyadic {
+    pub const ZERO: Self = Self {
+        whole: false,
+        bits: Vec::new(),
+    };
+
+    pub const ONE: Self = Self {
+        whole: true,
+        bits: Vec::new(),
+    };
+
+    /// The rational `numerator / 2^exponent`, or `None` when it is greater than one.
+    pub fn new(numerator: u128, exponent: u32) -> Option<Self> {
+        // Greater than one exactly when the numerator does not fit below the leading bit of 2^k
+        let bits = numerator
+            .checked_ilog2()
+            .map_or(0, |log| u64::from(log) + 1);
+        let one = u64::from(exponent) + 1;
+        if bits > one || (bits == one && !numerator.is_power_of_two()) {
+            return None;
+        }
+        if bits == one {
+            return Some(Self::ONE);
+        }
+
+        let bits = (1..=exponent).map(|i| {
+            let shift = exponent - i;
+            shift < u128::BITS && numerator >> shift & 1 == 1
+        });
+        Some(Self::from_bits(bits.collect()))
+    }
+
+    /// The smallest `k` such that the value is a multiple of `1 / 2^k`.
+    pub fn exponent(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// The value as `(numerator, denominator)` in lowest terms, or `None` when the denominator
+    /// does not fit.
+    pub fn to_ratio(&self) -> Option<(u128, u128)> {
+        if self.whole {
+            return Some((1, 1));
+        }
+        let denominator = 1u128.checked_shl(self.exponent().try_into().ok()?)?;
+        let numerator = self
+            .bits
+            .iter()
+            .fold(0u128, |n, bit| n << 1 | u128::from(*bit));
+        Some((numerator, denominator))
+    }
+
+    /// The nearest floating point value.
+    pub fn to_f64(&self) -> f64 {
+        if self.whole {
+            return 1.0;
+        }
+        let mut value = 0.0;
+        let mut weight = 0.5;
+        for bit in self.bits.iter().take(f64::MANTISSA_DIGITS as usize + 1) {
+            if *bit {
+                value += weight;
+            }
+            weight /= 2.0;
+        }
+        value
+    }
+
+    /// Adds `1 / 2^depth`, which must not carry past one.
+    fn add_unit(&mut self, depth: usize) {
+        if depth == 0 {
+            self.whole = true;
+            self.bits.clear();
+            return;
+        }
+
+        if self.bits.len() < depth {
+            self.bits.resize(depth, false);
+        }
+        let mut i = depth;
+        loop {
+            if i == 0 {
+                self.whole = true;
+                break;
+            }
+            if !self.bits[i - 1] {
+                self.bits[i - 1] = true;
+                break;
+            }
+            self.bits[i - 1] = false;
+            i -= 1;
+        }
+        self.trim();
+    }
+
+    /// The dyadic interval reached by following `path` down from the root.
+    pub(crate) fn interval(path: Vec<bool>) -> Range<Self> {
+        let depth = path.len();
+        let start = Self::from_bits(path);
+        let mut end = start.clone();
+        end.add_unit(depth);
+        start..end
+    }
+
+    fn from_bits(bits: Vec<bool>) -> Self {
+        let mut value = Self { whole: false, bits };
+        value.trim();
+        value
+    }
+
+    fn trim(&mut self) {
+        while self.bits.last() == Some(&false) {
+            self.bits.pop();
+        }
+    }
+
+    /// The id owning `[0, self)`.
+    fn prefix(&self) -> IdTree {
+        if self.whole {
+            return IdTree::One;
+        }
+
+        let mut id = IdTree::Zero;
+        for bit in self.bits.iter().rev() {
+            id = if *bit {
+                IdTree::node(IdTree::One, id)
+            } else {
+                IdTree::node(id, IdTree::Zero)
+            };
+        }
+        id
+    }
+}
+
+/// Prints `n/d`, or the binary expansion when the denominator does not fit in a `u128`.
+impl std::fmt::Display for Dyadic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self.to_ratio() {
+            Some((0, _)) => write!(f, "0"),
+            Some((n, 1)) => write!(f, "{n}"),
+            Some((n, d)) => write!(f, "{n}/{d}"),
+            None => {
+                write!(f, "0b0.")?;
+                for bit in &self.bits {
+                    write!(f, "{}", u8::from(*bit))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl IdTree {
+    /// The dyadic ranges `[start, end)` owned by the id, one per owned leaf from left to right.
+    pub fn intervals(&self) -> impl Iterator<Item = Range<Dyadic>> + '_ {
+        self.owned_paths().map(Dyadic::interval)
+    }
+
+    /// The paths from the root down to every owned leaf, from left to right.
+    fn owned_paths(&self) -> impl Iterator<Item = Vec<bool>> + '_ {
+        // Nodes still to visit with their depth and the side they hang off their parent
+        let mut stack = vec![(self, 0, false)];
+        let mut path = vec![];
+        std::iter::from_fn(move || {
+            while let Some((id, depth, right)) = stack.pop() {
+                if depth > 0 {
+                    path.truncate(depth - 1);
+                    path.push(right);
+                }
+                match id {
+                    IdTree::Zero => {}
+                    IdTree::One => return Some(path.clone()),
+                    IdTree::SubTree(l, r) => {
+                        stack.push((r, depth + 1, true));
+                        stack.push((l, depth + 1, false));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Builds the id owning the union of `intervals`; empty ranges are ignored.
+    pub fn from_intervals<I>(intervals: I) -> Self
+    where
+        I: IntoIterator<Item = Range<Dyadic>>,
+    {
+        intervals
+            .into_iter()
+            .filter(|range| range.start < range.end)
+            .map(|range| range.end.prefix().difference(&range.start.prefix()))
+            .fold(IdTree::Zero, IdTree::join)
+    }
+
+    /// The deepest a leaf is subdivided by [`IdTree::fork_weighted`].
+    pub const WEIGHTED_FORK_DEPTH: u32 = 16;
+
+    /// Consumes to create id_left and id_right, with id_left owning approximately `ratio` of the
+    /// interval owned by `self`.
+    ///
+    /// See [`IdTree::fork_weighted_to_depth`].
+    pub fn fork_weighted(self, ratio: f64) -> (Self, Self) {
+        self.fork_weighted_to_depth(ratio, Self::WEIGHTED_FORK_DEPTH)
+    }
+
+    /// Like [`IdTree::fork_weighted`], subdividing the leaf where the split falls at most `depth`
+    /// levels further, so the share is off by at most half of `1 / 2^depth` of that leaf.
+    ///
+    /// As with [`IdTree::fork`] the two ids are disjoint, join back into `self`, and are both
+    /// non-anonymous unless `self` is, so `ratio` is clamped to `[0, 1]` and then nudged off
+    /// the ends. A NaN `ratio` counts as zero.
+    pub fn fork_weighted_to_depth(self, ratio: f64, depth: u32) -> (Self, Self) {
+        let paths: Vec<_> = self.owned_paths().collect();
+        let Some(last) = paths.len().checked_sub(1) else {
+            return (IdTree::Zero, IdTree::Zero);
+        };
+
+        // Beyond the precision of the ratio further levels cannot improve the split
+        let depth = depth.clamp(1, f64::MANTISSA_DIGITS);
+        let units = 1u64 << depth;
+        let sizes: Vec<f64> = paths
+            .iter()
+            .map(|path| 0.5f64.powi(path.len().try_into().unwrap_or(i32::MAX)))
+            .collect();
+        let ratio = if ratio.is_nan() {
+            0.0
+        } else {
+            ratio.clamp(0.0, 1.0)
+        };
+        let target = ratio * sizes.iter().sum::<f64>();
+
+        // The leaf where the split falls and how many of its units go to the left
+        let mut cut = (last, units);
+        let mut seen = 0.0;
+        for (i, size) in sizes.iter().enumerate() {
+            if target < seen + size {
+                cut = (i, ((target - seen) / size * units as f64).round() as u64);
+                break;
+            }
+            seen += size;
+        }
+        let (leaf, left_units) = match cut {
+            (0, 0) => (0, 1),
+            (leaf, units_left) if leaf == last && units_left == units => (leaf, units - 1),
+            cut => cut,
+        };
+
+        let mut point = paths[leaf].clone();
+        point.extend((0..depth).rev().map(|i| left_units >> i & 1 == 1));
+        let mut point = Dyadic::from_bits(point);
+        if left_units == units {
+            point.add_unit(paths[leaf].len());
+        }
+
+        let prefix = point.prefix();
+        let forked = (self.intersection(&prefix), self.difference(&prefix));
+        self.drop_iteratively();
+        prefix.drop_iteratively();
+        forked
+    }
+
+    /// Hands approximately `fraction` of the interval owned by `self` to a new id, keeping the
+    /// rest. See [`IdTree::fork_weighted`].
+    pub fn split_off(&mut self, fraction: f64) -> Self {
+        let (kept, peer) = std::mem::take(self).fork_weighted(1.0 - fraction);
+        *self = kept;
+        peer
+    }
+
+    /// The exact fraction of the unit interval owned by the id.
+    pub fn measure(&self) -> Dyadic {
+        let mut measure = Dyadic::ZERO;
+        let mut stack = vec![(self, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            match id {
+                IdTree::Zero => {}
+                IdTree::One => measure.add_unit(depth),
+                IdTree::SubTree(l, r) => {
+                    stack.push((r, depth + 1));
+                    stack.push((l, depth + 1));
+                }
+            }
+        }
+        measure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dyadic(numerator: u128, exponent: u32) -> Dyadic {
+        Dyadic::new(numerator, exponent).expect("At most one")
+    }
+
+    #[test]
+    fn test_dyadic() {
+        assert_eq!(dyadic(0, 5), Dyadic::ZERO);
+        assert_eq!(dyadic(4, 2), Dyadic::ONE);
+        assert_eq!(dyadic(2, 3), dyadic(1, 2));
+        assert_eq!(Dyadic::new(5, 2), None);
+        assert_eq!(Dyadic::new(u128::MAX, 127), None);
+
+        assert!(dyadic(3, 3) < dyadic(1, 1));
+        assert!(dyadic(5, 3) > dyadic(1, 1));
+        assert_eq!(dyadic(6, 4).to_ratio(), Some((3, 8)));
+        assert_eq!(dyadic(6, 4).to_string(), "3/8");
+        assert_eq!(dyadic(6, 4).to_f64(), 0.375);
+        assert_eq!(dyadic(1, 200).exponent(), 200);
+        assert_eq!(dyadic(1, 200).to_ratio(), None);
+    }
+
+    #[test]
+    fn test_intervals() {
+        let (a, b) = IdTree::one().fork();
+        let (_, c) = b.fork();
+        let ac = a.clone().join(c.clone());
+
+        let intervals: Vec<_> = ac.intervals().collect();
+        assert_eq!(
+            intervals,
+            vec![dyadic(0, 0)..dyadic(1, 1), dyadic(3, 2)..dyadic(1, 0)]
+        );
+        assert_eq!(IdTree::from_intervals(intervals), ac);
+        assert_eq!(
+            IdTree::one().intervals().collect::<Vec<_>>(),
+            [Dyadic::ZERO..Dyadic::ONE]
+        );
+        assert_eq!(IdTree::zero().intervals().count(), 0);
+
+        // Arbitrary ranges are split into dyadic ones, and overlaps are merged
+        let id = IdTree::from_intervals([dyadic(1, 2)..dyadic(5, 3), dyadic(1, 2)..dyadic(1, 1)]);
+        assert_eq!(id.to_string(), "((0, 1), ((1, 0), 0))");
+        assert_eq!(id.measure().to_string(), "3/8");
+        assert_eq!(
+            IdTree::from_intervals([dyadic(1, 1)..dyadic(1, 2)]),
+            IdTree::zero()
+        );
+    }
+
+    #[test]
+    fn test_fork_weighted() {
+        let (l, r) = IdTree::one().fork_weighted(0.25);
+        assert_eq!(l.to_string(), "((1, 0), 0)");
+        assert_eq!(r.to_string(), "((0, 1), 1)");
+
+        let (l, r) = IdTree::one().fork_weighted(0.3);
+        assert!((l.measure().to_f64() - 0.3).abs() <= 0.5f64.powi(17));
+        assert_eq!(l.clone().join(r.clone()), IdTree::one());
+
+        let (l, r) = IdTree::one().fork_weighted_to_depth(0.3, 2);
+        assert_eq!(l.measure().to_string(), "1/4");
+        assert_eq!(r.measure().to_string(), "3/4");
+
+        // Whole leaves are handed over before any is subdivided
+        let (a, b) = IdTree::one().fork();
+        let (_, c) = b.fork();
+        let ac = a.clone().join(c.clone());
+        assert_eq!(ac.clone().fork_weighted(2.0 / 3.0), (a, c));
+
+        for ratio in [0.0, -1.0, 1.0, 2.0, f64::NAN, 0.999_999_999] {
+            for id in [IdTree::one(), ac.clone()] {
+                let (l, r) = id.clone().fork_weighted(ratio);
+                assert!(!l.is_anonymous() && !r.is_anonymous());
+                assert!(l.is_disjoint(&r));
+                assert_eq!(l.join(r), id);
+            }
+        }
+        assert_eq!(
+            IdTree::zero().fork_weighted(0.5),
+            (IdTree::zero(), IdTree::zero())
+        );
+    }
+
+    #[test]
+    fn test_split_off() {
+        let mut id = IdTree::one();
+        let peer = id.split_off(0.125);
+        assert_eq!(peer.measure().to_string(), "1/8");
+        assert_eq!(id.measure().to_string(), "7/8");
+
+        let worker = id.split_off(0.5);
+        assert_eq!(worker.measure(), id.measure());
+        assert_eq!(id.join(worker).join(peer), IdTree::one());
+    }
+
+    #[test]
+    fn test_measure() {
+        let (a, b) = IdTree::one().fork();
+        let (b, c) = b.fork();
+
+        assert_eq!(a.measure(), dyadic(1, 1));
+        assert_eq!(c.measure().to_string(), "1/4");
+        assert_eq!(a.join(c).measure().to_string(), "3/4");
+        assert_eq!(IdTree::one().measure(), Dyadic::ONE);
+        assert_eq!(IdTree::zero().measure(), Dyadic::ZERO);
+        assert_eq!(IdTree::subtree(b.clone(), b).measure().to_string(), "1/4");
+
+        let mut deep = IdTree::One;
+        for _ in 0..200 {
+            deep = IdTree::SubTree(Box::new(IdTree::Zero), Box::new(deep));
+        }
+        let measure = deep.measure();
+        assert_eq!(measure, dyadic(1, 200));
+        assert_eq!(measure.to_string().len(), "0b0.".len() + 200);
+    }
+}
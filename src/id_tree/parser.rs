@@ -1,61 +1,43 @@
 use super::*;
-use thiserror::Error;
+use crate::parser::{ParseError, ParseErrorKind, ParseOptions, Parser};
 
-#[derive(Debug, PartialEq, Eq, Error)]
-pub enum IdTreeParseError {
-    #[error("Invalid value encountered {0}")]
-    InvalidValue(u32),
+const EXPECTED_ID: &str = "`0`, `1` or `(`";
 
-    #[error("Unable to find the split")]
-    NoSplit,
-
-    #[error("Unknown characters")]
-    Unknown,
+impl IdTree {
+    /// Parses the `Display` representation of an `IdTree`, e.g. `(1, (0, 1))`.
+    pub fn parse_with(s: &str, options: &ParseOptions) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(s, options);
+        let id = parse_id(&mut parser)?;
+        parser.finish()?;
+        Ok(id)
+    }
 }
 
 impl std::str::FromStr for IdTree {
-    type Err = IdTreeParseError;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim();
-        if let Ok(val) = s.parse::<u32>() {
-            match val {
-                0 => Ok(IdTree::Zero),
-                1 => Ok(IdTree::One),
-                val => Err(IdTreeParseError::InvalidValue(val)),
-            }
-        } else if s.starts_with('(') && s.ends_with(')') {
-            let s = &s[1..s.len() - 1];
-
-            let (left, right) = if s.starts_with('(') {
-                let mut acc = 0;
-                let (idx, _) = s
-                    .char_indices()
-                    .take_while(|(_idx, c)| {
-                        match c {
-                            '(' => acc += 1,
-                            ')' => acc -= 1,
-                            _ => {}
-                        }
-
-                        acc != 0
-                    })
-                    .last()
-                    .ok_or(IdTreeParseError::NoSplit)?;
-
-                let (left, right) = s.split_at(idx + 2);
-                let right = &right[1..];
-                (left, right)
-            } else {
-                s.split_once(',').ok_or(IdTreeParseError::NoSplit)?
-            };
-
-            let left = left.parse::<Self>()?;
-            let right = right.parse::<Self>()?;
-            Ok(IdTree::subtree(left, right))
-        } else {
-            Err(IdTreeParseError::Unknown)
-        }
+        Self::parse_with(s, &ParseOptions::default())
+    }
+}
+
+/// id := `0` | `1` | `(` id `,` id `)`
+pub(crate) fn parse_id(parser: &mut Parser) -> Result<IdTree, ParseError> {
+    if parser.eat(b'(') {
+        parser.descend()?;
+        let left = parse_id(parser)?;
+        parser.expect(b',', "`,`")?;
+        let right = parse_id(parser)?;
+        parser.expect(b')', "`)`")?;
+        parser.ascend();
+        return Ok(IdTree::node(left, right));
+    }
+
+    let (digits, start) = parser.digits(EXPECTED_ID)?;
+    match digits {
+        "0" => Ok(IdTree::Zero),
+        "1" => Ok(IdTree::One),
+        _ => Err(parser.error_at(ParseErrorKind::InvalidValue, start, "`0` or `1`")),
     }
 }
 
@@ -72,8 +54,46 @@ mod tests {
         ];
 
         for s in strs {
-            let id: IdTree = s.parse().expect(&format!("Unable to parse {s}"));
+            let id: IdTree = s.parse().unwrap_or_else(|_| panic!("Unable to parse {s}"));
             assert_eq!(format!("{id}"), s);
         }
     }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = "((1, 0), (0 1))".parse::<IdTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 12);
+        assert_eq!(err.snippet, "1))");
+        assert_eq!(err.expected, "`,`");
+
+        let err = "(1, 2)".parse::<IdTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidValue);
+        assert_eq!(err.offset, 4);
+
+        let err = "(1, 0) 1".parse::<IdTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TrailingInput);
+        assert_eq!(err.offset, 7);
+
+        let err = "(1, (0".parse::<IdTree>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedEnd);
+        assert_eq!(err.offset, 6);
+
+        assert_eq!(
+            err.to_string(),
+            "Unexpected end of input at byte 6 near ``; expected `,`"
+        );
+    }
+
+    #[test]
+    fn test_parse_max_depth() {
+        let s = "(((1, 0), 0), 0)";
+        let options = ParseOptions::new().max_depth(2);
+        let err = IdTree::parse_with(s, &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TooDeep);
+        assert_eq!(err.offset, 3);
+
+        let options = ParseOptions::new().max_depth(3);
+        assert!(IdTree::parse_with(s, &options).is_ok());
+    }
 }
@@ -0,0 +1,162 @@
+//! Serde support with the same representation as a derived implementation, but refusing ids
+//! nested deeper than [`MAX_DECODE_DEPTH`](crate::MAX_DECODE_DEPTH) on the way in, so that
+//! untrusted input cannot overflow the stack. Use [`IdTree::deserialize_with`] to accept deeper
+//! ids.
+//!
+//! Serializing has no limit, so that every id the crate builds can be written out, but the
+//! serializer nests once per level just as a derived implementation would.
+use super::*;
+use crate::DecodeOptions;
+use serde::de::{self, DeserializeSeed, EnumAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::SerializeTupleVariant;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+const VARIANTS: &[&str] = &["Zero", "One", "SubTree"];
+const TOO_DEEP: &str = "Maximum nesting depth exceeded";
+
+impl Serialize for IdTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            IdTree::Zero => serializer.serialize_unit_variant("IdTree", 0, "Zero"),
+            IdTree::One => serializer.serialize_unit_variant("IdTree", 1, "One"),
+            IdTree::SubTree(l, r) => {
+                let mut s = serializer.serialize_tuple_variant("IdTree", 2, "SubTree", 2)?;
+                s.serialize_field(l)?;
+                s.serialize_field(r)?;
+                s.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IdTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::deserialize_with(deserializer, &DecodeOptions::default())
+    }
+}
+
+impl IdTree {
+    /// Deserializes an id nested at most [`DecodeOptions::max_depth`] deep. Deserializing
+    /// recurses once per level, so the limit should stay well within the stack.
+    pub fn deserialize_with<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        options: &DecodeOptions,
+    ) -> Result<Self, D::Error> {
+        Seed {
+            depth: 0,
+            max_depth: options.max_depth,
+        }
+        .deserialize(deserializer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(variant_identifier)]
+enum Variant {
+    Zero,
+    One,
+    SubTree,
+}
+
+/// Deserializes a subtree with `depth` subtrees above it.
+#[derive(Clone, Copy)]
+struct Seed {
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for Seed {
+    type Value = IdTree;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_enum("IdTree", VARIANTS, self)
+    }
+}
+
+impl<'de> Visitor<'de> for Seed {
+    type Value = IdTree;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("enum IdTree")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        match data.variant()? {
+            (Variant::Zero, variant) => variant.unit_variant().map(|_| IdTree::Zero),
+            (Variant::One, variant) => variant.unit_variant().map(|_| IdTree::One),
+            (Variant::SubTree, _) if self.depth >= self.max_depth => {
+                Err(de::Error::custom(TOO_DEEP))
+            }
+            (Variant::SubTree, variant) => variant.tuple_variant(
+                2,
+                SubTreeVisitor(Seed {
+                    depth: self.depth + 1,
+                    ..self
+                }),
+            ),
+        }
+    }
+}
+
+/// Deserializes the children of a subtree with `seed` for each.
+struct SubTreeVisitor(Seed);
+
+impl<'de> Visitor<'de> for SubTreeVisitor {
+    type Value = IdTree;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("tuple variant IdTree::SubTree")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let left = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let right = seq
+            .next_element_seed(self.0)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(IdTree::node(left, right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_DECODE_DEPTH;
+    use bincode::Options;
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let id: IdTree = "((1, 0), (0, 1))".parse().expect("Valid id");
+        let bytes = bincode::serialize(&id).expect("Shallow id");
+        let decoded: IdTree = bincode::deserialize(&bytes).expect("Valid id");
+        assert_eq!(decoded, id);
+    }
+
+    #[test]
+    fn test_serde_deep() {
+        // Forking more often than the default limit still serializes
+        let mut id = IdTree::one();
+        for _ in 0..=MAX_DECODE_DEPTH {
+            id = id.fork().1;
+        }
+        let bytes = bincode::serialize(&id).expect("Any id serializes");
+        assert!(bincode::deserialize::<IdTree>(&bytes).is_err());
+
+        let bincode = bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let options = DecodeOptions::new().max_depth(MAX_DECODE_DEPTH + 1);
+        let mut deserializer = bincode::Deserializer::from_slice(&bytes, bincode);
+        let decoded = IdTree::deserialize_with(&mut deserializer, &options).expect("Deep enough");
+        assert_eq!(decoded, id);
+    }
+
+    #[test]
+    fn test_serde_too_deep() {
+        // A crafted chain of subtrees far deeper than the stack could recurse through
+        let bytes = 2u32.to_le_bytes().repeat(100_000);
+        assert!(bincode::deserialize::<IdTree>(&bytes).is_err());
+    }
+}
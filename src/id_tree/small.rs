@@ -0,0 +1,278 @@
+use super::*;
+
+/// An id which keeps a single owned branch inline as a bit path, only allocating an [`IdTree`]
+/// for ids owning more than one branch or nested deeper than [`SmallIdTree::MAX_INLINE_DEPTH`].
+///
+/// Ids produced by repeated forking of `1`, such as `(0, (1, 0))`, never allocate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SmallIdTree(Repr);
+
+/// Ids which fit inline are always stored inline, so the derived equality is semantic.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Repr {
+    /// `0` for the anonymous id, otherwise the path below a leading marker bit, where each bit is
+    /// the side taken from the root down: `0` for left and `1` for right.
+    Path(u64),
+    Tree(IdTree),
+}
+
+impl SmallIdTree {
+    /// The deepest single branch which is kept inline.
+    pub const MAX_INLINE_DEPTH: u32 = u64::BITS - 1;
+
+    /// Initial tree is the full interval (1)
+    pub fn one() -> Self {
+        Self(Repr::Path(1))
+    }
+
+    /// Initial tree is the empty interval (0)
+    pub fn zero() -> Self {
+        Self(Repr::Path(0))
+    }
+
+    /// Whether the id is stored inline without any allocation.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Path(_))
+    }
+
+    /// Whether the id owns no part of the interval, i.e. is equivalent to `0`.
+    pub fn is_anonymous(&self) -> bool {
+        match &self.0 {
+            Repr::Path(path) => *path == 0,
+            Repr::Tree(id) => id.is_anonymous(),
+        }
+    }
+
+    /// Consumes to create id_left and id_right
+    pub fn fork(self) -> (Self, Self) {
+        match self.0 {
+            Repr::Path(0) => (Self::zero(), Self::zero()),
+            Repr::Path(path) if Self::depth(path) < Self::MAX_INLINE_DEPTH => {
+                (Self(Repr::Path(path << 1)), Self(Repr::Path(path << 1 | 1)))
+            }
+            repr => {
+                let (l, r) = IdTree::from(Self(repr)).fork();
+                (l.into(), r.into())
+            }
+        }
+    }
+
+    /// Consumes to merge two ids
+    pub fn join(self, other: Self) -> Self {
+        match (self.0, other.0) {
+            (Repr::Path(0), repr) | (repr, Repr::Path(0)) => Self(repr),
+            (Repr::Path(a), Repr::Path(b)) if a == b => Self(Repr::Path(a)),
+            // Siblings merge back into their parent
+            (Repr::Path(a), Repr::Path(b)) if a ^ b == 1 => Self(Repr::Path(a >> 1)),
+            (a, b) => IdTree::from(Self(a)).join(IdTree::from(Self(b))).into(),
+        }
+    }
+
+    /// The sides taken from the root down to the single owned leaf, or `None` when the id is
+    /// anonymous or stored as a tree.
+    pub(crate) fn path(&self) -> Option<impl Iterator<Item = bool>> {
+        match self.0 {
+            Repr::Path(0) | Repr::Tree(_) => None,
+            Repr::Path(path) => Some(
+                (0..Self::depth(path))
+                    .rev()
+                    .map(move |i| path >> i & 1 == 1),
+            ),
+        }
+    }
+
+    fn depth(path: u64) -> u32 {
+        Self::MAX_INLINE_DEPTH - path.leading_zeros()
+    }
+}
+
+impl Default for SmallIdTree {
+    fn default() -> Self {
+        Self::one()
+    }
+}
+
+/// Stores `id` inline whenever it owns a single branch which is shallow enough.
+impl From<IdTree> for SmallIdTree {
+    fn from(id: IdTree) -> Self {
+        let id = id.normalize();
+        let mut path = 1u64;
+        let mut node = &id;
+        loop {
+            match node {
+                IdTree::Zero => return Self::zero(),
+                IdTree::One => return Self(Repr::Path(path)),
+                IdTree::SubTree(..) if Self::depth(path) == Self::MAX_INLINE_DEPTH => break,
+                IdTree::SubTree(l, r) => match (l.as_ref(), r.as_ref()) {
+                    (l, IdTree::Zero) => {
+                        path <<= 1;
+                        node = l;
+                    }
+                    (IdTree::Zero, r) => {
+                        path = path << 1 | 1;
+                        node = r;
+                    }
+                    _ => break,
+                },
+            }
+        }
+        Self(Repr::Tree(id))
+    }
+}
+
+impl From<SmallIdTree> for IdTree {
+    fn from(id: SmallIdTree) -> Self {
+        match id.0 {
+            Repr::Tree(id) => id,
+            repr => IdTree::from(&SmallIdTree(repr)),
+        }
+    }
+}
+
+impl From<&SmallIdTree> for IdTree {
+    fn from(id: &SmallIdTree) -> Self {
+        let Some(path) = id.path() else {
+            return match &id.0 {
+                Repr::Tree(id) => id.clone(),
+                Repr::Path(_) => IdTree::Zero,
+            };
+        };
+
+        let mut tree = IdTree::One;
+        for right in path.collect::<Vec<_>>().into_iter().rev() {
+            tree = if right {
+                IdTree::SubTree(Box::new(IdTree::Zero), Box::new(tree))
+            } else {
+                IdTree::SubTree(Box::new(tree), Box::new(IdTree::Zero))
+            };
+        }
+        tree
+    }
+}
+
+impl PartialEq<IdTree> for SmallIdTree {
+    fn eq(&self, other: &IdTree) -> bool {
+        let Some(path) = self.path() else {
+            return match &self.0 {
+                Repr::Tree(id) => id == other,
+                Repr::Path(_) => other.is_anonymous(),
+            };
+        };
+
+        let mut node = other;
+        for right in path {
+            match node {
+                IdTree::Zero | IdTree::One => return false,
+                IdTree::SubTree(l, r) => {
+                    let (owned, other) = if right { (r, l) } else { (l, r) };
+                    if !other.is_anonymous() {
+                        return false;
+                    }
+                    node = owned;
+                }
+            }
+        }
+        *node == IdTree::One
+    }
+}
+
+impl PartialEq<SmallIdTree> for IdTree {
+    fn eq(&self, other: &SmallIdTree) -> bool {
+        other == self
+    }
+}
+
+impl std::fmt::Display for SmallIdTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let Some(path) = self.path() else {
+            return match &self.0 {
+                Repr::Tree(id) => write!(f, "{id}"),
+                Repr::Path(_) => write!(f, "0"),
+            };
+        };
+
+        let path: Vec<_> = path.collect();
+        for right in &path {
+            write!(f, "{}", if *right { "(0, " } else { "(" })?;
+        }
+        write!(f, "1")?;
+        for right in path.iter().rev() {
+            write!(f, "{}", if *right { ")" } else { ", 0)" })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fork_join() {
+        let (a, b) = SmallIdTree::one().fork();
+        let (b, c) = b.fork();
+        assert!(a.is_inline() && b.is_inline() && c.is_inline());
+        assert_eq!(b.to_string(), "(0, (1, 0))");
+        assert_eq!(c.to_string(), "(0, (0, 1))");
+
+        let (ia, ib) = IdTree::one().fork();
+        let (ib, ic) = ib.fork();
+        assert_eq!(a, ia);
+        assert_eq!(b, ib);
+        assert_eq!(c, ic);
+
+        // Siblings merge inline, anything else spills into a tree
+        let bc = b.clone().join(c.clone());
+        assert!(bc.is_inline());
+        assert_eq!(bc.to_string(), "(0, 1)");
+        let ac = a.clone().join(c);
+        assert!(!ac.is_inline());
+        assert_eq!(ac, ia.join(ic));
+
+        // Forking the spilled id gives back inline halves
+        let (l, r) = ac.fork();
+        assert!(l.is_inline() && r.is_inline());
+        assert_eq!(l, a);
+        assert_eq!(l.join(r).join(b), SmallIdTree::one());
+    }
+
+    #[test]
+    fn test_conversions() {
+        let id = IdTree::subtree(
+            IdTree::zero(),
+            IdTree::subtree(
+                IdTree::zero(),
+                IdTree::subtree(IdTree::one(), IdTree::zero()),
+            ),
+        );
+        let small = SmallIdTree::from(id.clone());
+        assert!(small.is_inline());
+        assert_eq!(small.to_string(), id.to_string());
+        assert_eq!(IdTree::from(&small), id);
+        assert_eq!(IdTree::from(small), id);
+
+        assert_eq!(SmallIdTree::from(IdTree::zero()), SmallIdTree::zero());
+        assert!(SmallIdTree::zero().is_anonymous());
+        assert_ne!(SmallIdTree::zero(), IdTree::one());
+        assert_ne!(
+            SmallIdTree::one(),
+            IdTree::subtree(IdTree::one(), IdTree::zero())
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut id = SmallIdTree::one();
+        let mut tree = IdTree::one();
+        for _ in 0..SmallIdTree::MAX_INLINE_DEPTH {
+            id = id.fork().1;
+            tree = tree.fork().1;
+        }
+        assert!(id.is_inline());
+        assert_eq!(id, tree);
+
+        let (l, r) = id.fork();
+        assert!(!l.is_inline() && !r.is_inline());
+        assert_eq!(l.join(r), tree);
+    }
+}
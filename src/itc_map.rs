@@ -1,29 +1,71 @@
-use crate::{EventTree, IdTree};
+use crate::{
+    Counter, EventError, EventTree, IdTree, OverflowError, OverflowPolicy, Relation, SmallIdTree,
+};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 
+mod encoding;
+
+/// Values keyed by the ids of the replicas which wrote them, along with the timestamp covering
+/// every write.
+///
+/// Like its trees, a map with ids or a timestamp many thousands of levels deep can overflow the
+/// stack when dropped; free it with [`ItcMap::drop_iteratively`].
 #[derive(Debug, Clone)]
-pub struct ItcMap<T> {
-    timestamp: EventTree,
+pub struct ItcMap<T, C: Counter = u64> {
+    timestamp: EventTree<C>,
     data: Vec<Option<(IdTree, T)>>,
     index: ItcIndex,
+    read_only: bool,
+    overflow_policy: OverflowPolicy,
 }
 
 impl<T> ItcMap<T> {
     pub fn new() -> Self {
         Self::default()
     }
+}
+
+impl<T, C: Counter> ItcMap<T, C> {
+    /// A map which can `apply` patches from its peers but refuses to record events of its own.
+    pub fn read_only() -> Self {
+        Self {
+            read_only: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-    pub fn from_patch(patch: Patch<T>) -> Self {
-        let mut new = Self::new();
-        new.apply(patch);
+    /// Sets how local events and applied patches handle a counter which would overflow.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    pub fn from_patch(patch: Patch<T, C>) -> Self {
+        let mut new = Self::default();
+        let (added, removed) = new.apply(patch);
+        for (id, _) in added {
+            id.drop_iteratively();
+        }
+        for (id, _) in removed {
+            id.drop_iteratively();
+        }
         new
     }
 
-    pub fn timestamp(&self) -> &EventTree {
+    pub fn timestamp(&self) -> &EventTree<C> {
         &self.timestamp
     }
 
+    /// The causal relation of this map's timestamp to `timestamp`.
+    pub fn relation(&self, timestamp: &EventTree<C>) -> Relation {
+        self.timestamp.relation(timestamp)
+    }
+
     pub fn get(&self, id: &IdTree) -> Option<&T> {
         self.index
             .get(id)
@@ -38,6 +80,20 @@ impl<T> ItcMap<T> {
             })
     }
 
+    /// Looks up `id` without allocating when it is stored inline.
+    pub fn get_small(&self, id: &SmallIdTree) -> Option<&T> {
+        self.index
+            .get_small(id)
+            .and_then(|idx| self.data[idx].as_ref())
+            .map(|(sid, d)| {
+                if id == sid {
+                    d
+                } else {
+                    panic!("ItcIndex out of sync with ItcMap")
+                }
+            })
+    }
+
     pub fn len(&self) -> usize {
         self.data.iter().filter_map(|x| x.as_ref()).count()
     }
@@ -53,17 +109,113 @@ impl<T> ItcMap<T> {
             .map(|(i, d)| (i, d))
     }
 
+    /// Iterates over the entries in the order of their ids, regardless of how the map was built.
+    ///
+    /// Unlike [`ItcMap::iter`], this collects and sorts the entries first.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (&IdTree, &T)> {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by_key(|(id, _)| *id);
+        entries.into_iter()
+    }
+
+    /// Inserts `value` for `id`, recording an event.
+    ///
+    /// # Panics
+    ///
+    /// Panics on a read-only map or an anonymous id; see [`ItcMap::try_insert`].
     pub fn insert(&mut self, id: IdTree, value: T) -> Vec<(IdTree, T)> {
-        self.update_timestamp(&id);
-        self.insert_without_event(id, value)
+        match self.try_insert(id, value) {
+            Ok(removed) => removed,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_insert(&mut self, id: IdTree, value: T) -> Result<Vec<(IdTree, T)>, EventError> {
+        self.update_timestamp(&id)?;
+        Ok(self.insert_without_event(id, value))
+    }
+
+    /// Inserts every entry in order, recording the events for all the entries sharing an id in a
+    /// single step, in the order the ids first appear. The map ends up with the same entries as if
+    /// each had been inserted on its own.
+    ///
+    /// # Panics
+    ///
+    /// Panics on a read-only map or an anonymous id; see [`ItcMap::try_insert_batch`].
+    pub fn insert_batch<I>(&mut self, entries: I) -> Vec<(IdTree, T)>
+    where
+        I: IntoIterator<Item = (IdTree, T)>,
+    {
+        match self.try_insert_batch(entries) {
+            Ok(removed) => removed,
+            Err(e) => panic!("{e}"),
+        }
     }
 
+    /// Like [`ItcMap::insert_batch`], failing without changing the map if the events for any of
+    /// the entries cannot be recorded.
+    pub fn try_insert_batch<I>(&mut self, entries: I) -> Result<Vec<(IdTree, T)>, EventError>
+    where
+        I: IntoIterator<Item = (IdTree, T)>,
+    {
+        if self.read_only {
+            return Err(EventError::ReadOnly);
+        }
+
+        let entries: Vec<_> = entries.into_iter().collect();
+        let mut counts: Vec<(&IdTree, u128)> = vec![];
+        let mut seen = HashMap::new();
+        for (id, _) in &entries {
+            let i = *seen.entry(id).or_insert_with(|| {
+                counts.push((id, 0));
+                counts.len() - 1
+            });
+            counts[i].1 += 1;
+        }
+
+        let mut timestamp = self.timestamp.clone();
+        let mut recorded = Ok(());
+        for (id, count) in counts {
+            let n = C::from_u128(count).ok_or(OverflowError);
+            recorded = n
+                .map_err(EventError::from)
+                .and_then(|n| timestamp.try_event_n_in_place_with(id, n, self.overflow_policy));
+            if recorded.is_err() {
+                break;
+            }
+        }
+        drop(seen);
+        if let Err(e) = recorded {
+            timestamp.drop_iteratively();
+            return Err(e);
+        }
+        std::mem::replace(&mut self.timestamp, timestamp).drop_iteratively();
+
+        let mut removed = vec![];
+        for (id, value) in entries {
+            removed.append(&mut self.insert_without_event(id, value));
+        }
+        Ok(removed)
+    }
+
+    /// Records an event for an `id` already in the map, returning whether it was present.
+    ///
+    /// # Panics
+    ///
+    /// Panics on a read-only map or when the timestamp overflows; see [`ItcMap::try_event`].
     pub fn event(&mut self, id: &IdTree) -> bool {
+        match self.try_event(id) {
+            Ok(recorded) => recorded,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_event(&mut self, id: &IdTree) -> Result<bool, EventError> {
         if self.index.get(id).is_some() {
-            self.update_timestamp(id);
-            true
+            self.update_timestamp(id)?;
+            Ok(true)
         } else {
-            false
+            Ok(false)
         }
     }
 
@@ -94,16 +246,35 @@ impl<T> ItcMap<T> {
         }
 
         self.index = index;
+        id.drop_iteratively();
 
         removed
     }
 
-    /// Returns (&Added, Removed)
-    pub fn apply<'a>(&'a mut self, mut patch: Patch<T>) -> (IdAdditions<'a, T>, IdRemovals<T>) {
+    /// Applies a patch from a peer. Returns (&Added, Removed)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the patch's timestamp overflows under [`OverflowPolicy::Error`]; see
+    /// [`ItcMap::try_apply`].
+    pub fn apply<'a>(&'a mut self, patch: Patch<T, C>) -> (IdAdditions<'a, T>, IdRemovals<T>) {
+        match self.try_apply(patch) {
+            Ok(applied) => applied,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Applies a patch from a peer, handling a timestamp which overflows according to the map's
+    /// overflow policy. Failing leaves the map as it was.
+    pub fn try_apply<'a>(
+        &'a mut self,
+        mut patch: Patch<T, C>,
+    ) -> Result<(IdAdditions<'a, T>, IdRemovals<T>), OverflowError> {
+        patch.timestamp = std::mem::take(&mut patch.timestamp).sanitize(self.overflow_policy)?;
+
         let mut removed = vec![];
         let mut added_ids = vec![];
 
-        let peer_time = patch.timestamp.clone();
         let time_diff = patch.timestamp.diff(&self.timestamp);
 
         for (id, val) in patch
@@ -116,8 +287,9 @@ impl<T> ItcMap<T> {
             added_ids.push(id);
         }
 
-        let ts = std::mem::take(&mut self.timestamp);
-        self.timestamp = ts.join(peer_time);
+        self.timestamp.join_assign(&patch.timestamp);
+        time_diff.drop_iteratively();
+        patch.timestamp.drop_iteratively();
 
         let added = added_ids
             .drain(..)
@@ -127,7 +299,16 @@ impl<T> ItcMap<T> {
             })
             .collect();
 
-        (added, removed)
+        Ok((added, removed))
+    }
+
+    /// Drops the map one tree node at a time, for maps whose timestamp or ids are too deep to
+    /// drop as usual; see [`EventTree::drop_iteratively`].
+    pub fn drop_iteratively(self) {
+        self.timestamp.drop_iteratively();
+        for (id, _) in self.data.into_iter().flatten() {
+            id.drop_iteratively();
+        }
     }
 
     fn allocate(&mut self, id: IdTree, value: T) -> usize {
@@ -140,17 +321,19 @@ impl<T> ItcMap<T> {
         }
     }
 
-    fn update_timestamp(&mut self, id: &IdTree) {
-        let ts = std::mem::take(&mut self.timestamp);
-        let ts = ts.event(id);
-        self.timestamp = ts;
+    fn update_timestamp(&mut self, id: &IdTree) -> Result<(), EventError> {
+        if self.read_only {
+            return Err(EventError::ReadOnly);
+        }
+        self.timestamp
+            .try_event_in_place_with(id, self.overflow_policy)
     }
 
-    pub fn map_recursive<U, L, C>(&self, leaf_fn: &L, combine_fn: &C) -> U
+    pub fn map_recursive<U, L, F>(&self, leaf_fn: &L, combine_fn: &F) -> U
     where
         U: Default,
         L: Fn(&T) -> U,
-        C: Fn(U, U) -> U,
+        F: Fn(U, U) -> U,
     {
         self.index.map_recursive(
             &|idx: usize| {
@@ -169,10 +352,10 @@ impl<T> ItcMap<T> {
 type IdAdditions<'a, T> = Vec<(IdTree, &'a T)>;
 type IdRemovals<T> = Vec<(IdTree, T)>;
 
-impl<T: Clone> ItcMap<T> {
-    pub fn diff(&self, timestamp: &EventTree) -> Option<Patch<T>> {
-        let time_diff = self.timestamp.clone().diff(timestamp);
-        if time_diff == EventTree::Leaf(0) {
+impl<T: Clone, C: Counter> ItcMap<T, C> {
+    pub fn diff(&self, timestamp: &EventTree<C>) -> Option<Patch<T, C>> {
+        let time_diff = self.timestamp.diff(timestamp);
+        if time_diff == EventTree::Leaf(C::ZERO) {
             return None;
         }
 
@@ -183,7 +366,8 @@ impl<T: Clone> ItcMap<T> {
             .map(|(id, d)| (id.clone(), d.clone()))
             .collect();
         let mask = self.timestamp.mask(&time_diff);
-        assert_ne!(mask, EventTree::Leaf(0));
+        time_diff.drop_iteratively();
+        assert_ne!(mask, EventTree::Leaf(C::ZERO));
         Some(Patch {
             timestamp: mask,
             inner,
@@ -191,7 +375,7 @@ impl<T: Clone> ItcMap<T> {
     }
 }
 
-impl<T: PartialEq> PartialEq for ItcMap<T> {
+impl<T: PartialEq, C: Counter> PartialEq for ItcMap<T, C> {
     fn eq(&self, other: &Self) -> bool {
         if self.timestamp != other.timestamp {
             return false;
@@ -215,20 +399,22 @@ impl<T: PartialEq> PartialEq for ItcMap<T> {
     }
 }
 
-impl<T> Default for ItcMap<T> {
+impl<T, C: Counter> Default for ItcMap<T, C> {
     fn default() -> Self {
         Self {
-            timestamp: EventTree::new(),
+            timestamp: EventTree::default(),
             data: vec![],
             index: ItcIndex::Unknown,
+            read_only: false,
+            overflow_policy: OverflowPolicy::default(),
         }
     }
 }
 
-impl<T: fmt::Display> fmt::Display for ItcMap<T> {
+impl<T: fmt::Display, C: Counter> fmt::Display for ItcMap<T, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         let data = self
-            .iter()
+            .iter_ordered()
             .map(|(id, d)| format!("{id}: {d}"))
             .collect::<Vec<_>>()
             .join(", ");
@@ -242,7 +428,7 @@ impl<T: fmt::Display> fmt::Display for ItcMap<T> {
 
 /// An ItcIndex provides lookup of all associated timestamp IDs for a given EventTree, as well as
 /// various merging capabilities with partial-trees.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum ItcIndex {
     #[default]
@@ -258,134 +444,188 @@ impl ItcIndex {
     }
 
     fn get(&self, id: &IdTree) -> Option<usize> {
-        match (self, id) {
-            (ItcIndex::Unknown, _) => None,
-            (_, IdTree::Zero) => None,
-            (ItcIndex::Leaf(idx), IdTree::One) => Some(*idx),
-            (ItcIndex::SubTree(l0, r0), IdTree::SubTree(l1, r1)) => {
-                if let Some(idx) = l0.get(l1) {
-                    Some(idx)
-                } else {
-                    r0.get(r1)
+        let mut stack = vec![(self, id)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (ItcIndex::Leaf(idx), IdTree::One) => return Some(*idx),
+                (ItcIndex::SubTree(l0, r0), IdTree::SubTree(l1, r1)) => {
+                    stack.push((r0, r1));
+                    stack.push((l0, l1));
                 }
+                // TODO: Should we handle partial-match cases? Are there any situations where our
+                // IdTree we're looking up is _almost_ valid?
+                _ => {}
             }
-            // TODO: Should we handle partial-match cases? Are there any situations where our
-            // IdTree we're looking up is _almost_ valid?
+        }
+        None
+    }
+
+    fn get_small(&self, id: &SmallIdTree) -> Option<usize> {
+        let Some(path) = id.path() else {
+            return self.get(&IdTree::from(id));
+        };
+
+        let mut index = self;
+        for right in path {
+            match index {
+                ItcIndex::SubTree(l, r) => index = if right { r } else { l },
+                _ => return None,
+            }
+        }
+        match index {
+            ItcIndex::Leaf(idx) => Some(*idx),
             _ => None,
         }
     }
 
     // Returns increments and Decrements
     fn insert(self, id: &IdTree, idx: usize) -> (ItcIndex, HashSet<usize>) {
-        match (self, id) {
-            (s, IdTree::Zero) => (s, HashSet::new()),
-            (ItcIndex::Unknown, IdTree::One) => (ItcIndex::Leaf(idx), HashSet::new()),
-            (ItcIndex::Unknown, IdTree::SubTree(l, r)) => {
-                let (l, _) = ItcIndex::Unknown.insert(l, idx);
-                let (r, _) = ItcIndex::Unknown.insert(r, idx);
-                (
-                    ItcIndex::SubTree(Box::new(l.norm()), Box::new(r.norm())),
-                    HashSet::new(),
-                )
-            }
-            (ItcIndex::Leaf(old), IdTree::One) => {
-                let mut d = HashSet::new();
-                d.insert(old);
-                (ItcIndex::Leaf(idx), d)
-            }
-            (ItcIndex::Leaf(old), IdTree::SubTree(l, r)) => {
-                let (l, _) = ItcIndex::Unknown.insert(l, idx);
-                let (r, _) = ItcIndex::Unknown.insert(r, idx);
-                let mut d = HashSet::new();
-                d.insert(old);
-                (ItcIndex::SubTree(Box::new(l.norm()), Box::new(r.norm())), d)
-            }
-            (ItcIndex::SubTree(l0, r0), IdTree::One) => {
-                let (_, mut lr) = l0.insert(&IdTree::One, idx);
-                let (_, rr) = r0.insert(&IdTree::One, idx);
-                lr.extend(rr);
-                (ItcIndex::Leaf(idx), lr)
-            }
-            (ItcIndex::SubTree(l0, r0), IdTree::SubTree(l1, r1)) => {
-                let (l, mut lr) = l0.insert(l1, idx);
-                let (r, rr) = r0.insert(r1, idx);
-                lr.extend(rr);
-                (
-                    ItcIndex::SubTree(Box::new(l.norm()), Box::new(r.norm())),
-                    lr,
-                )
+        use ItcIndex::*;
+
+        let mut removed = HashSet::new();
+        let mut frames = vec![Some((self, id))];
+        // Built subtrees, flagged when only their root may still need normalizing
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some((index, id)) = frame else {
+                let norm = |(child, built): (ItcIndex, bool)| {
+                    if built {
+                        child.norm_root()
+                    } else {
+                        child.norm()
+                    }
+                };
+                let r = norm(done.pop().expect("Right child is built last"));
+                let l = norm(done.pop().expect("Left child is built first"));
+                done.push((Self::subtree(l, r), true));
+                continue;
+            };
+
+            match (index, id) {
+                (index, IdTree::Zero) => done.push((index, false)),
+                (index, IdTree::One) => {
+                    removed.extend(index.leaves());
+                    done.push((Leaf(idx), true));
+                }
+                (index, IdTree::SubTree(l, r)) => {
+                    let (il, ir) = match index {
+                        index @ SubTree(..) => index.into_children(),
+                        Leaf(old) => {
+                            removed.insert(old);
+                            (Unknown, Unknown)
+                        }
+                        Unknown => (Unknown, Unknown),
+                    };
+                    frames.push(None);
+                    frames.push(Some((ir, r)));
+                    frames.push(Some((il, l)));
+                }
             }
         }
+
+        let (index, _) = done.pop().expect("The root is always built");
+        (index, removed)
     }
 
     fn norm(self) -> Self {
-        use ItcIndex::*;
-        match self {
-            SubTree(l, r) => {
-                let l = l.norm();
-                let r = r.norm();
-
-                match (&l, &r) {
-                    (Unknown, Unknown) => return Unknown,
-                    (Leaf(il), Leaf(ir)) if il == ir => return Leaf(*il),
-                    _ => {}
+        let mut frames = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(index @ ItcIndex::SubTree(..)) => {
+                    let (l, r) = index.into_children();
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                Some(index) => done.push(index),
+                None => {
+                    let r = done.pop().expect("Right child is normalized last");
+                    let l = done.pop().expect("Left child is normalized first");
+                    done.push(Self::subtree(l, r).norm_root());
                 }
-                SubTree(Box::new(l), Box::new(r))
             }
+        }
+        done.pop().expect("The root is always normalized")
+    }
+
+    /// Normalizes the root assuming both children are already normalized.
+    fn norm_root(self) -> Self {
+        use ItcIndex::*;
+        match &self {
+            SubTree(l, r) => match (l.as_ref(), r.as_ref()) {
+                (Unknown, Unknown) => Unknown,
+                (Leaf(il), Leaf(ir)) if il == ir => Leaf(*il),
+                _ => self,
+            },
             _ => self,
         }
     }
 
     fn purge(self, id: &IdTree, idx: usize) -> ItcIndex {
-        match (self, id) {
-            (s @ ItcIndex::Unknown, _) | (s, IdTree::Zero) => s,
-            (ItcIndex::Leaf(old), IdTree::One | IdTree::SubTree(..)) if old == idx => {
-                ItcIndex::Unknown
-            }
-            (ItcIndex::SubTree(l0, r0), IdTree::SubTree(l1, r1)) => {
-                let l = l0.purge(l1, idx);
-                let r = r0.purge(r1, idx);
-                ItcIndex::SubTree(Box::new(l), Box::new(r))
+        let mut frames = vec![Some((self, id))];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            let Some(pair) = frame else {
+                let r = done.pop().expect("Right child is purged last");
+                let l = done.pop().expect("Left child is purged first");
+                done.push(Self::subtree(l, r));
+                continue;
+            };
+
+            match pair {
+                (s @ ItcIndex::Unknown, _) | (s, IdTree::Zero) => done.push(s),
+                (ItcIndex::Leaf(old), IdTree::One | IdTree::SubTree(..)) if old == idx => {
+                    done.push(ItcIndex::Unknown)
+                }
+                (s @ ItcIndex::SubTree(..), IdTree::SubTree(l1, r1)) => {
+                    let (l0, r0) = s.into_children();
+                    frames.push(None);
+                    frames.push(Some((r0, r1)));
+                    frames.push(Some((l0, l1)));
+                }
+                (s, _) => done.push(s),
             }
-            (s, _) => s,
         }
+        done.pop().expect("The root is always purged")
     }
 
-    pub fn query(&self, timestamp: &EventTree) -> impl Iterator<Item = usize> {
-        self.query_recurse(timestamp).into_iter()
-    }
-
-    fn query_recurse(&self, timestamp: &EventTree) -> HashSet<usize> {
+    pub fn query<C: Counter>(&self, timestamp: &EventTree<C>) -> impl Iterator<Item = usize> {
         let mut idxs = HashSet::new();
 
-        match (self, timestamp) {
-            (ItcIndex::Unknown, _) => {}
-            (_, EventTree::Leaf(v)) if *v == 0 => {}
-            (ItcIndex::Leaf(idx), EventTree::Leaf(_)) => {
-                idxs.insert(*idx);
-            }
-            (ItcIndex::SubTree(l, r), e @ EventTree::Leaf(_)) => {
-                idxs.extend(l.query_recurse(e));
-                idxs.extend(r.query_recurse(e));
-            }
-            (ItcIndex::Leaf(idx), EventTree::SubTree(v, _, _)) if *v > 0 => {
-                idxs.insert(*idx);
-            }
-            (i @ ItcIndex::Leaf(_), EventTree::SubTree(_, l, r)) => {
-                idxs.extend(i.query_recurse(l));
-                idxs.extend(i.query_recurse(r));
-            }
-            (ItcIndex::SubTree(l, r), EventTree::SubTree(v, _, _)) if *v > 0 => {
-                idxs.extend(l.query_recurse(&EventTree::Leaf(1)));
-                idxs.extend(r.query_recurse(&EventTree::Leaf(1)));
-            }
-            (ItcIndex::SubTree(l0, r0), EventTree::SubTree(_, l1, r1)) => {
-                idxs.extend(l0.query_recurse(l1));
-                idxs.extend(r0.query_recurse(r1));
+        // Below a non-zero node every index reached has seen an event
+        let one = EventTree::Leaf(C::ONE);
+        let mut stack = vec![(self, timestamp)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (ItcIndex::Unknown, _) => {}
+                (_, EventTree::Leaf(v)) if *v == C::ZERO => {}
+                (ItcIndex::Leaf(idx), EventTree::Leaf(_)) => {
+                    idxs.insert(*idx);
+                }
+                (ItcIndex::SubTree(l, r), e @ EventTree::Leaf(_)) => {
+                    stack.push((l, e));
+                    stack.push((r, e));
+                }
+                (ItcIndex::Leaf(idx), EventTree::SubTree(v, _, _)) if *v > C::ZERO => {
+                    idxs.insert(*idx);
+                }
+                (i @ ItcIndex::Leaf(_), EventTree::SubTree(_, l, r)) => {
+                    stack.push((i, l));
+                    stack.push((i, r));
+                }
+                (ItcIndex::SubTree(l, r), EventTree::SubTree(v, _, _)) if *v > C::ZERO => {
+                    stack.push((l, &one));
+                    stack.push((r, &one));
+                }
+                (ItcIndex::SubTree(l0, r0), EventTree::SubTree(_, l1, r1)) => {
+                    stack.push((l0, l1));
+                    stack.push((r0, r1));
+                }
             }
         }
 
-        idxs
+        idxs.into_iter()
     }
 
     fn map_recursive<U, L, C>(&self, leaf_fn: &L, combine_fn: &C) -> U
@@ -394,13 +634,98 @@ impl ItcIndex {
         L: Fn(usize) -> U,
         C: Fn(U, U) -> U,
     {
-        match &self {
-            ItcIndex::Unknown => U::default(),
-            ItcIndex::Leaf(idx) => leaf_fn(*idx),
-            ItcIndex::SubTree(l, r) => combine_fn(
-                l.map_recursive(leaf_fn, combine_fn),
-                r.map_recursive(leaf_fn, combine_fn),
-            ),
+        let mut frames = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(ItcIndex::Unknown) => done.push(U::default()),
+                Some(ItcIndex::Leaf(idx)) => done.push(leaf_fn(*idx)),
+                Some(ItcIndex::SubTree(l, r)) => {
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let r = done.pop().expect("Right child is mapped last");
+                    let l = done.pop().expect("Left child is mapped first");
+                    done.push(combine_fn(l, r));
+                }
+            }
+        }
+        done.pop().expect("The root is always mapped")
+    }
+
+    /// Every index stored in the tree.
+    fn leaves(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            while let Some(index) = stack.pop() {
+                match index {
+                    ItcIndex::Unknown => {}
+                    ItcIndex::Leaf(idx) => return Some(*idx),
+                    ItcIndex::SubTree(l, r) => {
+                        stack.push(r);
+                        stack.push(l);
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Moves the children out of a subtree.
+    fn into_children(mut self) -> (Self, Self) {
+        match &mut self {
+            ItcIndex::SubTree(l, r) => (std::mem::take(&mut **l), std::mem::take(&mut **r)),
+            _ => unreachable!("Only subtrees have children"),
+        }
+    }
+}
+
+impl Clone for ItcIndex {
+    fn clone(&self) -> Self {
+        use ItcIndex::*;
+        let mut frames = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Some(Unknown) => done.push(Unknown),
+                Some(Leaf(idx)) => done.push(Leaf(*idx)),
+                Some(SubTree(l, r)) => {
+                    frames.push(None);
+                    frames.push(Some(r));
+                    frames.push(Some(l));
+                }
+                None => {
+                    let r = done.pop().expect("Right child is cloned last");
+                    let l = done.pop().expect("Left child is cloned first");
+                    done.push(Self::subtree(l, r));
+                }
+            }
+        }
+        done.pop().expect("The root is always cloned")
+    }
+}
+
+/// Unlinks subtrees one at a time so that dropping a deep index cannot overflow the stack.
+impl Drop for ItcIndex {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        self.detach_subtrees(&mut stack);
+        while let Some(mut index) = stack.pop() {
+            index.detach_subtrees(&mut stack);
+        }
+    }
+}
+
+impl ItcIndex {
+    fn detach_subtrees(&mut self, stack: &mut Vec<Self>) {
+        if let ItcIndex::SubTree(l, r) = self {
+            for child in [l, r] {
+                if matches!(**child, ItcIndex::SubTree(..)) {
+                    stack.push(std::mem::take(&mut **child));
+                }
+            }
         }
     }
 }
@@ -408,22 +733,46 @@ impl ItcIndex {
 impl fmt::Display for ItcIndex {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         use ItcIndex::*;
-        match self {
-            Unknown => write!(f, "?"),
-            Leaf(id) => write!(f, "{}", id),
-            SubTree(l, r) => write!(f, "[{}, {}]", l, r),
+
+        enum Token<'a> {
+            Index(&'a ItcIndex),
+            Text(&'static str),
+        }
+
+        let mut stack = vec![Token::Index(self)];
+        while let Some(token) = stack.pop() {
+            match token {
+                Token::Text(text) => write!(f, "{text}")?,
+                Token::Index(Unknown) => write!(f, "?")?,
+                Token::Index(Leaf(id)) => write!(f, "{id}")?,
+                Token::Index(SubTree(l, r)) => {
+                    write!(f, "[")?;
+                    stack.push(Token::Text("]"));
+                    stack.push(Token::Index(r));
+                    stack.push(Token::Text(", "));
+                    stack.push(Token::Index(l));
+                }
+            }
         }
+        Ok(())
     }
 }
 
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct Patch<T> {
-    timestamp: EventTree,
+pub struct Patch<T, C: Counter = u64> {
+    timestamp: EventTree<C>,
     inner: Vec<(IdTree, T)>,
 }
 
-impl<T: fmt::Display> fmt::Display for Patch<T> {
+impl<T, C: Counter> Patch<T, C> {
+    /// Checks the patch's timestamp for counters which overflow; see [`EventTree::validate`].
+    pub fn validate(&self) -> Result<(), OverflowError> {
+        self.timestamp.validate()
+    }
+}
+
+impl<T: fmt::Display, C: Counter> fmt::Display for Patch<T, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         let inner = self
             .inner
@@ -454,6 +803,124 @@ mod tests {
         assert_eq!(map.get(&i1), Some(&"world"));
     }
 
+    #[test]
+    fn test_insert_batch() {
+        let ids = IdTree::one().fork_many(3);
+        let entries: Vec<_> = [0, 0, 0, 1, 1, 0, 2, 2, 2, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(value, i)| (ids[i].clone(), value))
+            .collect();
+
+        let mut sequential: ItcMap<usize> = ItcMap::new();
+        let mut expected = vec![];
+        for (id, value) in entries.clone() {
+            expected.append(&mut sequential.insert(id, value));
+        }
+
+        let timestamp = EventTree::new()
+            .event_n(&ids[0], 4)
+            .event_n(&ids[1], 2)
+            .event_n(&ids[2], 4);
+
+        let mut batched = ItcMap::new();
+        assert_eq!(batched.insert_batch(entries), expected);
+        assert_eq!(batched.timestamp().to_string(), timestamp.to_string());
+        assert_eq!(
+            batched.iter_ordered().collect::<Vec<_>>(),
+            sequential.iter_ordered().collect::<Vec<_>>()
+        );
+        assert_eq!(batched.get(&ids[2]), Some(&9));
+
+        let mut read_only: ItcMap<usize> = ItcMap::read_only();
+        assert_eq!(
+            read_only.try_insert_batch([(ids[0].clone(), 1)]),
+            Err(EventError::ReadOnly)
+        );
+        assert!(read_only.is_empty());
+    }
+
+    #[test]
+    fn test_insert_batch_interleaved() {
+        let ids = IdTree::one().fork_many(3);
+        let entries: Vec<_> = [0, 1, 0, 2, 1, 0]
+            .into_iter()
+            .enumerate()
+            .map(|(value, i)| (ids[i].clone(), value))
+            .collect();
+
+        let mut sequential: ItcMap<usize> = ItcMap::new();
+        let mut expected = vec![];
+        for (id, value) in entries.clone() {
+            expected.append(&mut sequential.insert(id, value));
+        }
+
+        // One step per id, in the order the ids first appear
+        let timestamp = EventTree::new()
+            .event_n(&ids[0], 3)
+            .event_n(&ids[1], 2)
+            .event_n(&ids[2], 1);
+
+        let mut batched = ItcMap::new();
+        assert_eq!(batched.insert_batch(entries), expected);
+        assert_eq!(batched.timestamp().to_string(), timestamp.to_string());
+        assert_eq!(
+            batched.iter_ordered().collect::<Vec<_>>(),
+            sequential.iter_ordered().collect::<Vec<_>>()
+        );
+        assert_eq!(batched.get(&ids[0]), Some(&5));
+
+        // A batch that cannot record every event leaves the map as it was
+        let before = batched.clone();
+        assert_eq!(
+            batched.try_insert_batch([(ids[1].clone(), 6), (IdTree::zero(), 7)]),
+            Err(EventError::AnonymousId)
+        );
+        assert_eq!(
+            batched.timestamp().to_string(),
+            before.timestamp().to_string()
+        );
+        assert_eq!(batched, before);
+    }
+
+    #[test]
+    fn test_iter_order() {
+        let ids = IdTree::one().fork_many(4);
+        let mut forwards: ItcMap<usize> = ItcMap::new();
+        let mut backwards: ItcMap<usize> = ItcMap::new();
+        for (i, id) in ids.iter().enumerate() {
+            forwards.insert(id.clone(), i);
+        }
+        for (i, id) in ids.iter().enumerate().rev() {
+            backwards.insert(id.clone(), i);
+        }
+
+        let order: Vec<_> = forwards.iter_ordered().map(|(_, v)| *v).collect();
+        assert_eq!(order, [0, 1, 2, 3]);
+        assert!(forwards.iter_ordered().eq(backwards.iter_ordered()));
+
+        // Plain iteration follows the order of insertion
+        let order: Vec<_> = backwards.iter().map(|(_, v)| *v).collect();
+        assert_eq!(order, [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_get_small() {
+        let mut map: ItcMap<&'static str> = ItcMap::new();
+        let (a, b) = SmallIdTree::one().fork();
+        let (b, c) = b.fork();
+        let ac = a.clone().join(c.clone());
+        map.insert(b.clone().into(), "inline");
+        assert_eq!(map.get_small(&a), None);
+        assert_eq!(map.get_small(&c), None);
+
+        map.insert(ac.clone().into(), "spilled");
+        assert_eq!(map.get_small(&b), Some(&"inline"));
+        assert_eq!(map.get_small(&ac), Some(&"spilled"));
+        assert_eq!(map.get_small(&SmallIdTree::one()), None);
+        assert_eq!(map.get_small(&SmallIdTree::zero()), None);
+    }
+
     #[test]
     fn test_inserts_upsert() {
         let mut map: ItcMap<&'static str> = ItcMap::new();
@@ -610,6 +1077,112 @@ mod tests {
         assert_eq!(map2.timestamp().to_string(), "(1, 0, 1)".to_string());
     }
 
+    #[test]
+    fn test_relation() {
+        let mut ma: ItcMap<i32> = ItcMap::new();
+        let mut mb: ItcMap<i32> = ItcMap::new();
+        let (ia, ib) = IdTree::one().fork();
+
+        ma.insert(ia, 1);
+        assert_eq!(ma.relation(mb.timestamp()), Relation::HappenedAfter);
+
+        mb.insert(ib, 2);
+        assert_eq!(ma.relation(mb.timestamp()), Relation::Concurrent);
+
+        let patch = mb.diff(ma.timestamp()).expect("Some patch");
+        ma.apply(patch);
+        assert_eq!(mb.relation(ma.timestamp()), Relation::HappenedBefore);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut writer: ItcMap<i32> = ItcMap::new();
+        let mut reader: ItcMap<i32> = ItcMap::read_only();
+        let id = IdTree::one();
+
+        writer.insert(id.clone(), 7);
+        assert_eq!(
+            writer.try_insert(IdTree::zero(), 1),
+            Err(EventError::AnonymousId)
+        );
+
+        assert_eq!(reader.try_insert(id.clone(), 1), Err(EventError::ReadOnly));
+        assert!(reader.is_empty());
+
+        let patch = writer.diff(reader.timestamp()).expect("Some patch");
+        reader.apply(patch);
+        assert_eq!(reader.get(&id), Some(&7));
+        assert_eq!(reader.timestamp(), writer.timestamp());
+
+        assert_eq!(reader.try_event(&id), Err(EventError::ReadOnly));
+        assert_eq!(reader.try_event(&IdTree::zero()), Ok(false));
+        assert_eq!(reader.timestamp(), writer.timestamp());
+    }
+
+    #[test]
+    #[should_panic(expected = "read-only")]
+    fn test_read_only_event_panics() {
+        let mut writer: ItcMap<i32> = ItcMap::new();
+        writer.insert(IdTree::one(), 7);
+        let mut reader: ItcMap<i32> = ItcMap::read_only();
+        reader.apply(writer.diff(reader.timestamp()).expect("Some patch"));
+
+        reader.event(&IdTree::one());
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let mut map: ItcMap<i32> = ItcMap::new();
+        map.timestamp = EventTree::Leaf(u64::MAX);
+        assert_eq!(
+            map.try_insert(IdTree::one(), 1),
+            Err(EventError::Overflow(OverflowError))
+        );
+
+        let mut map = map.with_overflow_policy(OverflowPolicy::Saturate);
+        assert_eq!(map.try_insert(IdTree::one(), 1), Ok(vec![]));
+        assert_eq!(map.timestamp(), &EventTree::Leaf(u64::MAX));
+
+        let patch = map.diff(&EventTree::new()).expect("Some patch");
+        assert_eq!(patch.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_overflow() {
+        let mut writer: ItcMap<i32> = ItcMap::new();
+        writer.insert(IdTree::one(), 7);
+        let corrupt = || {
+            let mut patch = writer.diff(&EventTree::new()).expect("Some patch");
+            patch.timestamp = EventTree::subtree(u64::MAX, EventTree::Leaf(0), EventTree::Leaf(1));
+            patch
+        };
+
+        let mut reader: ItcMap<i32> = ItcMap::read_only();
+        assert_eq!(reader.try_apply(corrupt()).err(), Some(OverflowError));
+        assert!(reader.is_empty());
+        assert_eq!(reader.timestamp(), &EventTree::new());
+
+        let mut reader: ItcMap<i32> =
+            ItcMap::read_only().with_overflow_policy(OverflowPolicy::Saturate);
+        let (added, removed) = reader.try_apply(corrupt()).expect("Saturated patch");
+        assert_eq!(added, vec![(IdTree::one(), &7)]);
+        assert!(removed.is_empty());
+        assert_eq!(reader.timestamp(), &EventTree::Leaf(u64::MAX));
+    }
+
+    #[test]
+    fn test_counter_width() {
+        let mut ma: ItcMap<i32, u32> = ItcMap::default();
+        let mut mb: ItcMap<i32, u32> = ItcMap::read_only();
+
+        ma.insert(IdTree::one(), 1);
+        let patch = ma.diff(mb.timestamp()).expect("Some patch");
+        mb.apply(patch);
+
+        assert_eq!(mb.get(&IdTree::one()), Some(&1));
+        assert_eq!(mb.timestamp(), &EventTree::Leaf(1u32));
+    }
+
     #[test]
     fn test_patches_none() {
         let mut m: ItcMap<i32> = ItcMap::new();
@@ -619,21 +1192,23 @@ mod tests {
 
         m.insert(il.clone(), 2);
 
-        assert!(matches!(m.diff(m.timestamp()), None));
+        assert!(m.diff(m.timestamp()).is_none());
     }
 
     #[test]
     fn test_patches_skew() {
-        let mut map0 = ItcMap {
+        let mut map0: ItcMap<_> = ItcMap {
             timestamp: EventTree::Leaf(5),
             index: ItcIndex::subtree(ItcIndex::Leaf(0), ItcIndex::Leaf(1)),
             data: vec![
                 Some((IdTree::subtree(IdTree::One, IdTree::Zero), "foo")),
                 Some((IdTree::subtree(IdTree::Zero, IdTree::One), "bar")),
             ],
+            read_only: false,
+            overflow_policy: OverflowPolicy::default(),
         };
 
-        let map1 = ItcMap {
+        let map1: ItcMap<_> = ItcMap {
             timestamp: EventTree::subtree(4, EventTree::Leaf(2), EventTree::Leaf(0)),
             index: ItcIndex::subtree(
                 ItcIndex::subtree(ItcIndex::Leaf(0), ItcIndex::Leaf(2)),
@@ -650,13 +1225,22 @@ mod tests {
                     "baz",
                 )),
             ],
+            read_only: false,
+            overflow_policy: OverflowPolicy::default(),
         };
 
         assert_eq!(
             map0.to_string(),
             "TS:5 INDEX:[0, 1] DATA:{ (1, 0): foo, (0, 1): bar }".to_string()
         );
-        assert_eq!(map1.to_string(), "TS:(4, 2, 0) INDEX:[[0, 2], 1] DATA:{ ((1, 0), 0): foo, (0, 1): bar, ((0, 1), 0): baz }".to_string());
+        // Display lists the entries ordered by id, where iter keeps the order they were inserted in
+        assert_eq!(map1.to_string(), "TS:(4, 2, 0) INDEX:[[0, 2], 1] DATA:{ ((1, 0), 0): foo, ((0, 1), 0): baz, (0, 1): bar }".to_string());
+        let inserted = map1
+            .iter()
+            .map(|(id, d)| format!("{id}: {d}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(inserted, "((1, 0), 0): foo, (0, 1): bar, ((0, 1), 0): baz");
 
         let patch = map1.diff(map0.timestamp()).expect("Some patch");
         map0.apply(patch);
@@ -694,4 +1278,43 @@ mod tests {
         assert_eq!(res0, -31);
         assert_eq!(res1, 7);
     }
+
+    #[test]
+    fn test_deep_ids() {
+        const DEPTH: usize = 50_000;
+
+        // The rightmost of `DEPTH` nested halvings of the interval
+        let mut rest = IdTree::One;
+        for _ in 0..DEPTH {
+            rest = IdTree::SubTree(Box::new(IdTree::Zero), Box::new(rest));
+        }
+        let (first, _) = IdTree::one().fork();
+
+        let mut map: ItcMap<usize> = ItcMap::new();
+        map.insert(rest.clone(), DEPTH);
+        map.insert(first, 0);
+        assert_eq!(map.get(&rest), Some(&DEPTH));
+        let clone = map.clone();
+        assert_eq!(clone, map);
+        assert!(map.to_string().contains(&format!("{rest}: {DEPTH}")));
+        assert_eq!(map.map_recursive(&|x| *x, &|x, y| x + y), DEPTH);
+
+        let patch = map.diff(&EventTree::new()).expect("Some patch");
+        let copy = ItcMap::from_patch(patch);
+        assert_eq!(copy.get(&rest), Some(&DEPTH));
+
+        // Claiming the whole interval purges both entries
+        let removed = map.insert(IdTree::one(), 1);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(map.len(), 1);
+
+        // Dropping as usual would recurse once per level
+        for map in [map, clone, copy] {
+            map.drop_iteratively();
+        }
+        for (id, _) in removed {
+            id.drop_iteratively();
+        }
+        rest.drop_iteratively();
+    }
 }
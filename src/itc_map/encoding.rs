@@ -0,0 +1,95 @@
+use super::*;
+use crate::encoding::{BitReader, BitWriter, DecodeError, DecodeOptions};
+
+impl<T, C: Counter> Patch<T, C> {
+    /// Encodes the patch as its timestamp, the number of entries and then each id followed by a
+    /// length-prefixed value produced by `encode_value`.
+    pub fn encode<F>(&self, mut encode_value: F) -> Vec<u8>
+    where
+        F: FnMut(&T) -> Vec<u8>,
+    {
+        let mut writer = BitWriter::new();
+        self.timestamp.encode_into(&mut writer);
+        writer.write_counter(self.inner.len() as u64);
+
+        for (id, value) in &self.inner {
+            id.encode_into(&mut writer);
+            let bytes = encode_value(value);
+            writer.write_counter(bytes.len() as u64);
+            writer.write_bytes(&bytes);
+        }
+
+        writer.finish()
+    }
+
+    /// Decodes a patch produced by [`Patch::encode`], failing if `decode_value` rejects a value.
+    pub fn decode<F>(bytes: &[u8], decode_value: F) -> Result<Self, DecodeError>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        Self::decode_with(bytes, &DecodeOptions::default(), decode_value)
+    }
+
+    pub fn decode_with<F>(
+        bytes: &[u8],
+        options: &DecodeOptions,
+        mut decode_value: F,
+    ) -> Result<Self, DecodeError>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        let mut reader = BitReader::with_options(bytes, options);
+        let timestamp = EventTree::decode_from(&mut reader)?;
+        let count = reader.read_counter::<u64>()?;
+
+        let mut inner = vec![];
+        for _ in 0..count {
+            let id = IdTree::decode_from(&mut reader)?;
+            let start = reader.position();
+            let len = reader.read_counter::<u64>()?;
+            let len = usize::try_from(len).map_err(|_| DecodeError::Overflow(start))?;
+            let value =
+                decode_value(&reader.read_bytes(len)?).ok_or(DecodeError::InvalidValue(start))?;
+            inner.push((id, value));
+        }
+
+        reader.finish()?;
+        Ok(Self { timestamp, inner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut map: ItcMap<String> = ItcMap::new();
+        let (i0, i1) = IdTree::one().fork();
+        let (i1, i2) = i1.fork();
+        map.insert(i0.clone(), "hello".to_string());
+        map.insert(i1.clone(), "world".to_string());
+        map.insert(i2.clone(), String::new());
+
+        let patch = map.diff(&EventTree::new()).expect("Some patch");
+        let bytes = patch.encode(|v| v.as_bytes().to_vec());
+        let decoded =
+            Patch::decode(&bytes, |b| String::from_utf8(b.to_vec()).ok()).expect("Valid patch");
+
+        assert_eq!(decoded.timestamp, patch.timestamp);
+        assert_eq!(decoded.inner, patch.inner);
+        assert_eq!(ItcMap::from_patch(decoded), map);
+    }
+
+    #[test]
+    fn test_decode_invalid_value() {
+        let mut map: ItcMap<u8> = ItcMap::new();
+        map.insert(IdTree::one(), 7);
+
+        let patch = map.diff(&EventTree::new()).expect("Some patch");
+        let bytes = patch.encode(|v| vec![*v]);
+
+        let decoded = Patch::<u8>::decode(&bytes, |b| b.first().copied().filter(|v| *v != 7));
+        assert!(matches!(decoded, Err(DecodeError::InvalidValue(_))));
+    }
+}
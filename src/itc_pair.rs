@@ -1,28 +1,46 @@
-use crate::{EventTree, IdTree};
+use crate::{Counter, EventError, EventTree, IdTree, OverflowPolicy, OverlapError, Relation};
+
+mod encoding;
+#[cfg(feature = "parse")]
+mod parser;
 
 /// Higher level construct around the Id Tree and Event Tree primitives. Provides a higher level
 /// abstraction than the original paper.
-#[derive(Debug, Clone, Default)]
+///
+/// Like its trees, a pair many thousands of levels deep can overflow the stack when dropped; free
+/// it with [`ItcPair::drop_iteratively`].
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct ItcPair {
+pub struct ItcPair<C: Counter = u64> {
     pub id: IdTree,
-    pub timestamp: EventTree,
+    pub timestamp: EventTree<C>,
 }
 
 impl ItcPair {
     pub fn new() -> Self {
         Self::default()
     }
+}
 
+impl<C: Counter> ItcPair<C> {
     pub fn from(id: IdTree) -> Self {
         Self {
             id,
-            timestamp: EventTree::new(),
+            timestamp: EventTree::default(),
         }
     }
 
-    pub fn fork(&mut self) -> ItcPair {
-        ItcPair {
+    /// A replica with an anonymous id, which can `sync` and `join` but never records events.
+    pub fn read_only() -> Self {
+        Self::from(IdTree::zero())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.id.is_anonymous()
+    }
+
+    pub fn fork(&mut self) -> Self {
+        Self {
             id: self.fork_id(),
             timestamp: self.timestamp.clone(),
         }
@@ -35,26 +53,87 @@ impl ItcPair {
         other
     }
 
-    pub fn join(&mut self, other: ItcPair) {
-        self.sync(&other.timestamp);
+    pub fn join(&mut self, other: Self) {
+        self.timestamp.join_assign(&other.timestamp);
 
         let id = std::mem::take(&mut self.id);
         self.id = id.join(other.id);
     }
 
-    pub fn sync(&mut self, other: &EventTree) {
-        let other = other.clone();
-        let timestamp = std::mem::take(&mut self.timestamp);
-        self.timestamp = timestamp.join(other);
+    /// Cooperatively swaps the ids of two replicas for a compact pair owning the same part of the
+    /// interval, after syncing both timestamps so that neither can record an event under its
+    /// new id which the other's history already covers. See [`IdTree::refork`].
+    ///
+    /// Fails without changing either replica when their ids overlap.
+    pub fn refork(&mut self, other: &mut Self) -> Result<(), OverlapError> {
+        let (a, b) = self.id.clone().refork(other.id.clone())?;
+        self.timestamp.join_assign(&other.timestamp);
+        other.timestamp.clone_from(&self.timestamp);
+        self.id = a;
+        other.id = b;
+        Ok(())
+    }
+
+    /// Drops the pair one tree node at a time; see [`EventTree::drop_iteratively`].
+    pub fn drop_iteratively(self) {
+        self.id.drop_iteratively();
+        self.timestamp.drop_iteratively();
+    }
+
+    pub fn sync(&mut self, other: &EventTree<C>) {
+        self.timestamp.join_assign(other);
     }
 
+    /// The causal relation of this pair's timestamp to `timestamp`.
+    pub fn relation(&self, timestamp: &EventTree<C>) -> Relation {
+        self.timestamp.relation(timestamp)
+    }
+
+    /// Records an event.
+    ///
+    /// # Panics
+    ///
+    /// Panics on a read-only replica; see [`ItcPair::try_event`].
     pub fn event(&mut self) {
-        let timestamp = std::mem::take(&mut self.timestamp);
-        self.timestamp = timestamp.event(&self.id);
+        if let Err(e) = self.try_event() {
+            panic!("{e}");
+        }
+    }
+
+    pub fn try_event(&mut self) -> Result<(), EventError> {
+        self.try_event_n(C::ONE)
+    }
+
+    /// Records `n` events in one step, as if calling [`ItcPair::event`] `n` times.
+    ///
+    /// # Panics
+    ///
+    /// Panics on a read-only replica; see [`ItcPair::try_event_n`].
+    pub fn event_n(&mut self, n: C) {
+        if let Err(e) = self.try_event_n(n) {
+            panic!("{e}");
+        }
+    }
+
+    pub fn try_event_n(&mut self, n: C) -> Result<(), EventError> {
+        if self.is_read_only() {
+            return Err(EventError::ReadOnly);
+        }
+        self.timestamp
+            .try_event_n_in_place_with(&self.id, n, OverflowPolicy::Error)
+    }
+}
+
+impl<C: Counter> Default for ItcPair<C> {
+    fn default() -> Self {
+        Self {
+            id: IdTree::default(),
+            timestamp: EventTree::default(),
+        }
     }
 }
 
-impl std::fmt::Display for ItcPair {
+impl<C: Counter> std::fmt::Display for ItcPair<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "{} | {}", self.id, self.timestamp)
     }
@@ -89,6 +168,43 @@ mod tests {
         assert_eq!(&n0.to_string(), "1 | 2");
     }
 
+    #[test]
+    fn test_relation() {
+        let mut n0 = ItcPair::new();
+        let mut n1 = n0.fork();
+
+        assert_eq!(n0.relation(&n1.timestamp), Relation::Equal);
+
+        n0.event();
+        assert_eq!(n0.relation(&n1.timestamp), Relation::HappenedAfter);
+        assert_eq!(n1.relation(&n0.timestamp), Relation::HappenedBefore);
+
+        n1.event();
+        assert_eq!(n0.relation(&n1.timestamp), Relation::Concurrent);
+
+        n1.sync(&n0.timestamp);
+        assert_eq!(n0.relation(&n1.timestamp), Relation::HappenedBefore);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut n0 = ItcPair::new();
+        let mut reader = ItcPair::read_only();
+        assert!(reader.is_read_only());
+        assert!(!n0.is_read_only());
+
+        n0.event();
+        assert_eq!(reader.try_event(), Err(EventError::ReadOnly));
+
+        reader.sync(&n0.timestamp);
+        assert_eq!(reader.to_string(), "0 | 1");
+
+        // Joining hands the read-only replica an id, after which it may record events
+        reader.join(n0);
+        reader.try_event().expect("No longer read-only");
+        assert_eq!(reader.to_string(), "1 | 2");
+    }
+
     #[test]
     fn test_difference() {
         let mut n0 = ItcPair::new();
@@ -107,4 +223,75 @@ mod tests {
 
         assert_eq!(&diff.to_string(), "(0, (0, 1, 0), 0)");
     }
+
+    #[test]
+    fn test_event_n() {
+        let mut n0 = ItcPair::new();
+        let mut n1 = n0.fork();
+        n1.event();
+        n0.sync(&n1.timestamp);
+
+        let mut sequential = n0.clone();
+        for _ in 0..1000 {
+            sequential.event();
+        }
+        n0.event_n(1000);
+        assert_eq!(n0.timestamp, sequential.timestamp);
+        assert_eq!(n0.to_string(), "(1, 0) | (1, 999, 0)");
+
+        let mut reader: ItcPair = ItcPair::read_only();
+        assert_eq!(reader.try_event_n(3), Err(EventError::ReadOnly));
+    }
+
+    #[test]
+    fn test_refork() {
+        let mut n0 = ItcPair::new();
+        let mut n1 = n0.fork();
+        let mut n2 = n1.fork();
+        let mut n3 = n0.fork();
+
+        // n0 ends up with two pieces of the interval on either side of n3's
+        n1.event();
+        n2.event();
+        n3.event();
+        n0.join(n2);
+        assert_eq!(n0.id.to_string(), "((1, 0), (0, 1))");
+        assert_eq!(n3.id.to_string(), "((0, 1), 0)");
+
+        n0.refork(&mut n3).expect("Disjoint ids");
+        assert_eq!(n0.id.to_string(), "(1, 0)");
+        assert_eq!(n3.id.to_string(), "(0, (0, 1))");
+        assert_eq!(n0.relation(&n3.timestamp), Relation::Equal);
+        assert_eq!(n0.relation(&n1.timestamp), Relation::Concurrent);
+
+        // Every event afterwards still happens after everything seen before
+        let before = n0.timestamp.clone();
+        n0.event();
+        n3.event();
+        assert_eq!(n0.timestamp.relation(&before), Relation::HappenedAfter);
+        assert_eq!(n3.timestamp.relation(&before), Relation::HappenedAfter);
+
+        let mut copy = n0.clone();
+        let overlap = copy.id.clone();
+        assert_eq!(n0.refork(&mut copy), Err(OverlapError { overlap }));
+        assert_eq!(n0.id, copy.id);
+    }
+
+    #[test]
+    fn test_counter_width() {
+        let mut n0: ItcPair<u32> = ItcPair::default();
+        let mut n1 = n0.fork();
+
+        n0.event();
+        n1.event();
+        n1.event();
+        assert_eq!(n0.relation(&n1.timestamp), Relation::Concurrent);
+
+        n0.join(n1);
+        assert_eq!(n0.to_string(), "1 | (1, 0, 1)");
+        assert_eq!(
+            n0.timestamp,
+            EventTree::<u32>::subtree(1, EventTree::Leaf(0), EventTree::Leaf(1))
+        );
+    }
 }
@@ -0,0 +1,64 @@
+use super::*;
+use crate::encoding::{BitReader, BitWriter, DecodeError, DecodeOptions};
+
+impl<C: Counter> ItcPair<C> {
+    /// Encodes the id followed by the timestamp as a single bit stream.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        self.id.encode_into(&mut writer);
+        self.timestamp.encode_into(&mut writer);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_with(bytes, &DecodeOptions::default())
+    }
+
+    pub fn decode_with(bytes: &[u8], options: &DecodeOptions) -> Result<Self, DecodeError> {
+        let mut reader = BitReader::with_options(bytes, options);
+        let id = IdTree::decode_from(&mut reader)?;
+        let timestamp = EventTree::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(Self { id, timestamp })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_golden() {
+        // (1, 0) | (0, 1, 0) is 10 001 followed by 0 01 1001
+        let pair: ItcPair = ItcPair {
+            id: IdTree::subtree(IdTree::One, IdTree::Zero),
+            timestamp: EventTree::subtree(0, EventTree::Leaf(1), EventTree::Leaf(0)),
+        };
+        let bytes = vec![0x89, 0x90];
+
+        assert_eq!(pair.encode(), bytes);
+        assert_eq!(
+            ItcPair::<u64>::decode(&bytes)
+                .expect("Valid pair")
+                .to_string(),
+            pair.to_string()
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut n0 = ItcPair::new();
+        let mut n1 = n0.fork();
+        let n2 = n1.fork();
+
+        n0.event();
+        n1.event();
+        n1.event();
+        n0.join(n2);
+        n0.sync(&n1.timestamp);
+
+        let decoded = ItcPair::decode(&n0.encode()).expect("Valid pair");
+        assert_eq!(decoded.id, n0.id);
+        assert_eq!(decoded.timestamp, n0.timestamp);
+    }
+}
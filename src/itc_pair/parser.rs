@@ -0,0 +1,65 @@
+use super::*;
+use crate::event_tree::parser::parse_event;
+use crate::id_tree::parser::parse_id;
+use crate::parser::{ParseError, ParseOptions, Parser};
+
+impl<C: Counter> ItcPair<C> {
+    /// Parses the `Display` representation of an `ItcPair`, e.g. `(1, 0) | (0, 1, 0)`.
+    pub fn parse_with(s: &str, options: &ParseOptions) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(s, options);
+        let id = parse_id(&mut parser)?;
+        parser.expect(b'|', "`|`")?;
+        let timestamp = parse_event(&mut parser)?;
+        parser.finish()?;
+        Ok(ItcPair { id, timestamp })
+    }
+}
+
+impl<C: Counter> std::str::FromStr for ItcPair<C> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &ParseOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseErrorKind;
+
+    #[test]
+    fn test_parse() {
+        let strs = [
+            "1 | 0",
+            "(1, 0) | (0, 1, 0)",
+            "(1, (0, 1)) | (0, 2, (0, 0, 1))",
+            "((1, 0), 0) | (3, (0, 18446744073709551612, 0), 0)",
+        ];
+
+        for s in strs {
+            let pair: ItcPair = s.parse().unwrap_or_else(|_| panic!("Unable to parse {s}"));
+            assert_eq!(format!("{pair}"), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = "(1, 0) (0, 1, 0)".parse::<ItcPair>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 7);
+        assert_eq!(err.expected, "`|`");
+
+        let err = "(1, 2) | 0".parse::<ItcPair>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidValue);
+        assert_eq!(err.offset, 4);
+
+        let err = "1 | (0, 1)".parse::<ItcPair>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.offset, 9);
+
+        let err = "1 | 0 | 0".parse::<ItcPair>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TrailingInput);
+        assert_eq!(err.offset, 6);
+    }
+}
@@ -1,11 +1,24 @@
 #![doc = include_str!("../README.md")]
 
+mod counter;
+mod encoding;
 mod event_tree;
+mod flat;
 mod id_tree;
 mod itc_map;
 mod itc_pair;
+#[cfg(feature = "parse")]
+mod parser;
 
-pub use event_tree::EventTree;
-pub use id_tree::IdTree;
+pub use counter::Counter;
+pub use encoding::{DecodeError, DecodeOptions, MAX_DECODE_DEPTH};
+pub use event_tree::{
+    EventError, EventInterner, EventTree, FlatEventTree, GrowthStrategy, MinDepthGrowth,
+    MinSizeGrowth, OverflowError, OverflowPolicy, PaperGrowth, Relation, SharedEventTree, TotalOrd,
+};
+pub use id_tree::{Dyadic, FlatIdTree, IdTree, OverlapError, SmallIdTree};
 pub use itc_map::{ItcMap, Patch};
 pub use itc_pair::ItcPair;
+
+#[cfg(feature = "parse")]
+pub use parser::{DEFAULT_MAX_DEPTH, ParseError, ParseErrorKind, ParseOptions};
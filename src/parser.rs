@@ -0,0 +1,191 @@
+//! Shared recursive-descent machinery for the text formats produced by the `Display` impls.
+//!
+//! The grammar for each type lives next to the type itself; this module only provides the
+//! cursor, the error type and the options shared between them.
+use crate::Counter;
+use thiserror::Error;
+
+/// The default maximum nesting depth accepted when parsing.
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// How many characters of the input are included in a [`ParseError`] snippet.
+const SNIPPET_LEN: usize = 16;
+
+/// Options controlling how text is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    max_depth: usize,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of nested subtrees allowed before parsing fails.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedToken,
+    UnexpectedEnd,
+    InvalidValue,
+    TrailingInput,
+    TooDeep,
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        use ParseErrorKind::*;
+        match self {
+            UnexpectedToken => write!(f, "Unexpected token"),
+            UnexpectedEnd => write!(f, "Unexpected end of input"),
+            InvalidValue => write!(f, "Invalid value"),
+            TrailingInput => write!(f, "Trailing input"),
+            TooDeep => write!(f, "Maximum nesting depth exceeded"),
+        }
+    }
+}
+
+/// A parse failure, pointing at the byte offset in the input where it occurred.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{kind} at byte {offset} near `{snippet}`; expected {expected}")]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: usize,
+    pub snippet: String,
+    pub expected: &'static str,
+}
+
+pub(crate) struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(input: &'a str, options: &ParseOptions) -> Self {
+        Self {
+            input,
+            pos: 0,
+            depth: 0,
+            max_depth: options.max_depth,
+        }
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    pub(crate) fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    /// Consumes `token` if it is the next non-whitespace character.
+    pub(crate) fn eat(&mut self, token: u8) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn expect(&mut self, token: u8, expected: &'static str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    /// Parses a run of ascii digits, returning the digits and the offset they started at.
+    pub(crate) fn digits(
+        &mut self,
+        expected: &'static str,
+    ) -> Result<(&'a str, usize), ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let len = self.input[start..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+
+        if len == 0 {
+            return Err(self.unexpected(expected));
+        }
+
+        self.pos += len;
+        Ok((&self.input[start..self.pos], start))
+    }
+
+    pub(crate) fn counter<C: Counter>(&mut self) -> Result<C, ParseError> {
+        let (digits, start) = self.digits("a counter")?;
+        digits.parse().map_err(|_| {
+            self.error_at(
+                ParseErrorKind::InvalidValue,
+                start,
+                "a counter within the range of its type",
+            )
+        })
+    }
+
+    /// Tracks entry into a nested subtree, failing once the maximum depth is exceeded.
+    pub(crate) fn descend(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(self.error_at(ParseErrorKind::TooDeep, self.pos, "a shallower subtree"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn ascend(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Ensures the whole input was consumed.
+    pub(crate) fn finish(mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error_at(ParseErrorKind::TrailingInput, self.pos, "end of input")),
+        }
+    }
+
+    pub(crate) fn unexpected(&mut self, expected: &'static str) -> ParseError {
+        let kind = match self.peek() {
+            Some(_) => ParseErrorKind::UnexpectedToken,
+            None => ParseErrorKind::UnexpectedEnd,
+        };
+        self.error_at(kind, self.pos, expected)
+    }
+
+    pub(crate) fn error_at(
+        &self,
+        kind: ParseErrorKind,
+        offset: usize,
+        expected: &'static str,
+    ) -> ParseError {
+        let snippet = self.input[offset..].chars().take(SNIPPET_LEN).collect();
+        ParseError {
+            kind,
+            offset,
+            snippet,
+            expected,
+        }
+    }
+}